@@ -0,0 +1,590 @@
+use crate::{
+    client::{fallback_direction, notify, ClientError, Config, GameOutcome, Player},
+    replay::{Origin, Recorder},
+    types::{Direction, GameMode, GameSettings, InboundMessage, Map, OutboundMessage},
+};
+use clap::crate_version;
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use log::{debug, info, warn};
+use rustc_version::version;
+use std::{
+    mem, panic,
+    time::{Duration, Instant},
+};
+use target_info::Target;
+use tokio::{
+    net::TcpStream,
+    task::{self, JoinError, JoinHandle},
+    time,
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+#[cfg(feature = "tls")]
+use tokio_tungstenite::Connector;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Without TLS there is nothing to configure, so there is never a connector
+#[cfg(not(feature = "tls"))]
+#[derive(Clone)]
+enum Connector {}
+
+// How long to wait for the server to answer a close before dropping the connection
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ConnectionState {
+    Connecting,
+    Open,
+    NextGame,
+    Finished,
+    // Reconnecting would fail the same way
+    Failed,
+}
+
+// Plays on a tokio runtime, so several clients can share one process. Moves are computed on the blocking thread pool
+// and when one is not ready by the deadline, a fallback move is sent and the late answer is dropped. Until the player
+// is done, every tick gets a fallback move.
+pub struct AsyncClient;
+
+impl AsyncClient {
+    pub async fn connect<P, F>(config: Config, create_player: F) -> Result<Vec<GameOutcome>, ClientError>
+    where
+        P: Player + Send + 'static,
+        F: FnOnce() -> P,
+    {
+        let connection_url = config.connection_url();
+        let mut player = PlayerHandle::new(create_player());
+        let mut outcomes = Vec::new();
+        let mut recorder = match config.replay_directory {
+            Some(ref directory) => Some(Recorder::new(directory)?),
+            None => None,
+        };
+        let connector = tls_connector(&config)?;
+        let mut attempt = 0;
+        let mut was_connected = false;
+
+        let result = loop {
+            info!("Connecting to {:?}", connection_url);
+            let (state, error, registered) = match open(&connection_url, &connector).await {
+                Ok(socket) => {
+                    let (sink, mut stream) = socket.split();
+                    let mut session = Session {
+                        player: &mut player,
+                        config: &config,
+                        sink,
+                        recorder: &mut recorder,
+                        outcomes: &mut outcomes,
+                        state: ConnectionState::Open,
+                        error: None,
+                        player_id: None,
+                        game_mode: None,
+                        next_heartbeat: None,
+                        heartbeat_sent: None,
+                        closing_until: None,
+                        reconnect_attempt: if was_connected { attempt } else { 0 },
+                        time_to_move: time_to_move(&config, GameSettings::default().time_in_ms_per_tick),
+                        missed_deadlines: 0,
+                    };
+                    if let Err(error) = session.run(&mut stream).await {
+                        warn!("{}", error);
+                        session.set_error(error);
+                    }
+                    (session.state, session.error, session.player_id.is_some())
+                }
+                Err(error) => {
+                    warn!("{}", error);
+                    (ConnectionState::Connecting, Some(error), false)
+                }
+            };
+
+            match state {
+                ConnectionState::Finished => break Ok(()),
+                ConnectionState::Failed => {
+                    break Err(error.unwrap_or(ClientError::Disconnected { attempts: 0, cause: None }))
+                }
+                ConnectionState::NextGame => {
+                    was_connected = false;
+                    attempt = 0;
+                    continue;
+                }
+                // The player was registered, so start over with the shortest delay
+                ConnectionState::Open if registered => {
+                    was_connected = true;
+                    attempt = 1;
+                }
+                // A server that drops the connection before registering the player counts as a failed attempt
+                ConnectionState::Open => {
+                    was_connected = true;
+                    attempt += 1;
+                }
+                ConnectionState::Connecting => attempt += 1,
+            }
+
+            if attempt > config.reconnect_attempts {
+                break Err(ClientError::Disconnected {
+                    attempts: config.reconnect_attempts,
+                    cause: error.map(Box::new),
+                });
+            }
+
+            let delay = config.reconnect_delay(attempt);
+            info!(
+                "Connection lost, reconnecting in {:?} (attempt {} of {})",
+                delay, attempt, config.reconnect_attempts
+            );
+            time::sleep(delay).await;
+        };
+
+        // The player still gets the messages that arrived while it was thinking
+        player.settle().await;
+        result.map(|()| outcomes)
+    }
+}
+
+async fn open(connection_url: &str, connector: &Option<Connector>) -> Result<Socket, ClientError> {
+    let socket = handshake(connection_url, connector.clone()).await?;
+    info!("WebSocket opened");
+    Ok(socket)
+}
+
+// Trusts `tls_ca_file` besides the system certificates. It is read once, retrying would not fix a bad file.
+#[cfg(feature = "tls")]
+fn tls_connector(config: &Config) -> Result<Option<Connector>, ClientError> {
+    let file = match config.tls_ca_file {
+        Some(ref file) => file,
+        None => return Ok(None),
+    };
+    const END_CERTIFICATE: &str = "-----END CERTIFICATE-----";
+    let invalid =
+        |cause: Box<dyn std::error::Error + Send + Sync>| ClientError::InvalidTlsCaFile { file: file.clone(), cause };
+    let pem = std::fs::read_to_string(file).map_err(|error| invalid(error.into()))?;
+
+    // `Certificate::from_pem` only reads the first certificate, so a bundle is split up first
+    let mut builder = native_tls::TlsConnector::builder();
+    let mut certificates = 0;
+    for block in pem.split_inclusive(END_CERTIFICATE).filter(|block| block.contains(END_CERTIFICATE)) {
+        let certificate = native_tls::Certificate::from_pem(block.as_bytes()).map_err(|error| invalid(error.into()))?;
+        builder.add_root_certificate(certificate);
+        certificates += 1;
+    }
+    if certificates == 0 {
+        return Err(invalid("no certificate found".into()));
+    }
+    Ok(Some(Connector::NativeTls(builder.build().map_err(|error| invalid(error.into()))?)))
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls_connector(_: &Config) -> Result<Option<Connector>, ClientError> {
+    Ok(None)
+}
+
+#[cfg(feature = "tls")]
+async fn handshake(connection_url: &str, connector: Option<Connector>) -> Result<Socket, ClientError> {
+    let (socket, _) = tokio_tungstenite::connect_async_tls_with_config(connection_url, None, true, connector).await?;
+    Ok(socket)
+}
+
+#[cfg(not(feature = "tls"))]
+async fn handshake(connection_url: &str, _: Option<Connector>) -> Result<Socket, ClientError> {
+    let (socket, _) = tokio_tungstenite::connect_async_with_config(connection_url, None, true).await?;
+    Ok(socket)
+}
+
+// The tick minus `move_margin_ms`, or half the tick if the margin would not leave the player any time at all
+fn time_to_move(config: &Config, time_in_ms_per_tick: u32) -> Duration {
+    let tick = u64::from(time_in_ms_per_tick);
+    if config.move_margin_ms < tick {
+        return Duration::from_millis(tick - config.move_margin_ms);
+    }
+    warn!(
+        "move_margin_ms ({}) is not shorter than a tick ({} ms), using a margin of {} ms instead",
+        config.move_margin_ms,
+        tick,
+        tick / 2
+    );
+    Duration::from_millis(tick - tick / 2)
+}
+
+// One connection to the server
+struct Session<'a, P> {
+    player: &'a mut PlayerHandle<P>,
+    config: &'a Config,
+    sink: SplitSink<Socket, Message>,
+    recorder: &'a mut Option<Recorder>,
+    outcomes: &'a mut Vec<GameOutcome>,
+    state: ConnectionState,
+    // Why the connection failed or was closed, the first error wins
+    error: Option<ClientError>,
+    player_id: Option<String>,
+    // As reported by the server, which knows better than a venue guessed from the URL
+    game_mode: Option<GameMode>,
+    next_heartbeat: Option<Instant>,
+    // When the unanswered heartbeat was sent
+    heartbeat_sent: Option<Instant>,
+    // Set once the client has asked to close the connection
+    closing_until: Option<Instant>,
+    reconnect_attempt: u32,
+    // How long the player has from receiving a map update until the fallback move is sent
+    time_to_move: Duration,
+    missed_deadlines: u32,
+}
+
+impl<'a, P: Player + Send + 'static> Session<'a, P> {
+    async fn run(&mut self, stream: &mut SplitStream<Socket>) -> Result<(), ClientError> {
+        if self.reconnect_attempt > 0 {
+            self.player.call(Call::Reconnect(self.reconnect_attempt));
+        }
+
+        self.send_message(OutboundMessage::ClientInfo {
+            language: "Rust",
+            language_version: &version().unwrap().to_string(),
+            operating_system: Target::os(),
+            operating_system_version: "???",
+            client_version: crate_version!(),
+        })
+        .await?;
+
+        let config = self.config;
+        self.send_message(OutboundMessage::RegisterPlayer {
+            player_name: &config.snake_name,
+            game_settings: Default::default(),
+        })
+        .await?;
+
+        loop {
+            let wake_up = self.wake_up();
+            let message = tokio::select! {
+                message = stream.next() => message,
+                _ = time::sleep_until(wake_up.unwrap_or_else(Instant::now).into()), if wake_up.is_some() => {
+                    if self.on_timeout().await? {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+
+            match message {
+                Some(Ok(Message::Text(text))) => self.on_message(&text).await?,
+                Some(Ok(Message::Close(frame))) => self.on_close(frame),
+                Some(Ok(_)) => {}
+                Some(Err(error)) => return Err(error.into()),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn wake_up(&self) -> Option<Instant> {
+        if self.closing_until.is_some() {
+            return self.closing_until;
+        }
+        match self.heartbeat_sent {
+            Some(sent) => Some(sent + Duration::from_millis(self.config.heartbeat_timeout_ms)),
+            None => self.next_heartbeat,
+        }
+    }
+
+    // Returns true if the connection should be dropped
+    async fn on_timeout(&mut self) -> Result<bool, ClientError> {
+        if self.closing_until.is_some() {
+            warn!("The server did not answer the close within {:?}, dropping the connection", CLOSE_TIMEOUT);
+            return Ok(true);
+        }
+        if self.heartbeat_sent.is_some() {
+            // A half-open connection would never finish the closing handshake, so drop it
+            warn!("No heartbeat response within {} ms, dropping the connection", self.config.heartbeat_timeout_ms);
+            self.set_error(ClientError::HeartbeatTimeout);
+            return Ok(true);
+        }
+
+        if let Some(player_id) = self.player_id.clone() {
+            self.send_message(OutboundMessage::HeartBeatRequest { receiving_player_id: &player_id }).await?;
+            let now = Instant::now();
+            self.heartbeat_sent = Some(now);
+            self.next_heartbeat = Some(now + Duration::from_millis(self.config.heartbeat_interval_ms));
+        }
+        Ok(false)
+    }
+
+    fn on_close(&mut self, frame: Option<CloseFrame>) {
+        let (code, reason) =
+            frame.map_or((CloseCode::Status, String::new()), |frame| (frame.code, frame.reason.into()));
+        info!("WebSocket closed with code {:?} and reason: {}", code, reason);
+        if code == CloseCode::Normal && self.state == ConnectionState::Open {
+            self.state = ConnectionState::Finished;
+        } else if code != CloseCode::Normal {
+            self.set_error(ClientError::Closed { code, reason });
+        }
+    }
+
+    async fn on_message(&mut self, text: &str) -> Result<(), ClientError> {
+        let received = Instant::now();
+        self.record(Origin::Server, text);
+        let message = match InboundMessage::from_json(text) {
+            Ok(message) => message,
+            Err(error) => {
+                // Dropping the connection over one message would forfeit the game
+                warn!("Ignoring message that could not be read: {}: {}", error, text);
+                return Ok(());
+            }
+        };
+        debug!("Received message: {:?}", message);
+
+        self.player.notify(&message);
+
+        match message {
+            InboundMessage::PlayerRegistered { name, game_mode, receiving_player_id, game_settings, .. } => {
+                info!("Successfully registered player {}", name);
+                self.time_to_move = time_to_move(self.config, game_settings.time_in_ms_per_tick);
+                if game_mode == GameMode::Training {
+                    self.send_message(OutboundMessage::StartGame).await?;
+                }
+                self.player_id = Some(receiving_player_id);
+                self.game_mode = Some(game_mode);
+                self.next_heartbeat = Some(Instant::now() + Duration::from_millis(self.config.heartbeat_interval_ms));
+            }
+
+            InboundMessage::InvalidPlayerName { reason, .. } => {
+                info!("Player name invalid.");
+                self.set_error(ClientError::InvalidPlayerName(reason));
+                self.state = ConnectionState::Failed;
+                self.close().await?;
+            }
+
+            InboundMessage::HeartBeatResponse { .. } => {
+                if let Some(sent) = self.heartbeat_sent.take() {
+                    let round_trip = sent.elapsed();
+                    debug!("Heartbeat round trip took {:?}", round_trip);
+                    self.player.call(Call::Heartbeat(round_trip));
+                }
+            }
+
+            InboundMessage::GameStarting { game_settings, .. } => {
+                info!("All snakes are ready to rock. Game is starting.");
+                self.time_to_move = time_to_move(self.config, game_settings.time_in_ms_per_tick);
+                self.missed_deadlines = 0;
+            }
+
+            InboundMessage::GameLink { url, .. } => {
+                info!("Watch game at: {}", url);
+            }
+
+            InboundMessage::MapUpdate { map, game_id, game_tick, receiving_player_id, .. } => {
+                debug!("Game map updated, tick: {}", game_tick);
+
+                let deadline = received + self.time_to_move;
+                let direction = self.next_move(map, &receiving_player_id, game_tick, deadline).await;
+                self.send_message(OutboundMessage::RegisterMove {
+                    direction,
+                    game_tick,
+                    receiving_player_id: &receiving_player_id,
+                    game_id: &game_id,
+                })
+                .await?;
+            }
+
+            InboundMessage::SnakeDead { death_reason, .. } => {
+                debug!("The snake died, the reason was: {:?}", death_reason);
+            }
+
+            InboundMessage::GameEnded { player_winner_id, game_id, game_tick, map, .. } => {
+                info!("Game ended, the winner is: {}", player_winner_id);
+                let noof_games = self.record_outcome(game_id, game_tick, player_winner_id, &map);
+                let training =
+                    self.game_mode.map_or(self.config.venue == "training", |mode| mode == GameMode::Training);
+                if training {
+                    // Training games end the session, so play the next one on a new connection
+                    if self.config.games.is_none_or(|games| noof_games < games as usize) {
+                        self.state = ConnectionState::NextGame;
+                    }
+                    self.close().await?;
+                }
+            }
+
+            InboundMessage::TournamentEnded { player_winner_id, .. } => {
+                info!("Tournament ended, the winner is: {}", player_winner_id);
+                self.state = ConnectionState::Finished;
+                self.close().await?;
+            }
+
+            InboundMessage::Unknown { type_name, .. } => {
+                warn!("Ignoring message of unknown type {}", type_name);
+            }
+
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Asks the player on the blocking thread pool, settling for a fallback move if the answer is not in by the deadline
+    async fn next_move(&mut self, map: Map, player_id: &str, game_tick: u32, deadline: Instant) -> Direction {
+        let fallback = fallback_direction(&map, player_id);
+        match self.player.next_move(map, player_id, deadline).await {
+            Some(direction) => direction,
+            None => {
+                self.missed_deadlines += 1;
+                warn!(
+                    "Missed the deadline for tick {}, a fallback move was sent instead ({} missed this game)",
+                    game_tick, self.missed_deadlines
+                );
+                fallback
+            }
+        }
+    }
+
+    fn record_outcome(&mut self, game_id: String, game_tick: u32, player_winner_id: String, map: &Map) -> usize {
+        let player_id = self.player_id.as_ref().map_or("", String::as_str);
+        let outcome = GameOutcome {
+            won: player_winner_id == player_id,
+            points: map.get_snake_by_id(player_id).map_or(0, |snake| snake.points),
+            missed_deadlines: self.missed_deadlines,
+            game_id,
+            game_tick,
+            player_winner_id,
+        };
+        info!(
+            "Game {} ended after {} ticks, won: {}, points: {}, missed deadlines: {}",
+            outcome.game_id, game_tick, outcome.won, outcome.points, outcome.missed_deadlines
+        );
+
+        self.outcomes.push(outcome);
+        self.outcomes.len()
+    }
+
+    async fn send_message(&mut self, message: OutboundMessage<'_>) -> Result<(), ClientError> {
+        debug!("Sending message: {:?}", message);
+        let json_string = serde_json::to_string(&message)?;
+        self.record(Origin::Client, &json_string);
+        self.sink.send(Message::Text(json_string)).await?;
+        Ok(())
+    }
+
+    // Starts the closing handshake, the server ends the connection once it answers
+    async fn close(&mut self) -> Result<(), ClientError> {
+        self.sink.send(Message::Close(Some(CloseFrame { code: CloseCode::Normal, reason: "".into() }))).await?;
+        self.closing_until = Some(Instant::now() + CLOSE_TIMEOUT);
+        Ok(())
+    }
+
+    // Keeps the first error, later ones are usually caused by it
+    fn set_error(&mut self, error: ClientError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    fn record(&mut self, origin: Origin, text: &str) {
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.record_or_warn(origin, text);
+        }
+    }
+}
+
+// A call to the player that has to wait until it is back from a move
+enum Call {
+    Message(InboundMessage),
+    Reconnect(u32),
+    Heartbeat(Duration),
+}
+
+// Owns the player and lends it to a blocking task for each move, so a move that overruns its deadline does not hold
+// up the connection. Calls made while the player is away are queued and made in order once it is back.
+struct PlayerHandle<P> {
+    player: Option<P>,
+    thinking: Option<JoinHandle<(P, Direction)>>,
+    queued: Vec<Call>,
+}
+
+impl<P: Player + Send + 'static> PlayerHandle<P> {
+    fn new(player: P) -> PlayerHandle<P> {
+        PlayerHandle { player: Some(player), thinking: None, queued: Vec::new() }
+    }
+
+    fn notify(&mut self, message: &InboundMessage) {
+        match self.player {
+            Some(ref mut player) => notify(player, message),
+            None => self.queued.push(Call::Message(message.clone())),
+        }
+    }
+
+    fn call(&mut self, call: Call) {
+        match self.player {
+            Some(ref mut player) => make_call(player, call),
+            None => self.queued.push(call),
+        }
+    }
+
+    // None if the player did not answer by the deadline. A player still busy with an earlier tick gets until this
+    // deadline to come back, and its late answer is dropped.
+    async fn next_move(&mut self, map: Map, player_id: &str, deadline: Instant) -> Option<Direction> {
+        if self.thinking.is_some() {
+            self.wait_until(deadline).await?;
+        }
+        let mut player = self.player.take()?;
+        let player_id = player_id.to_string();
+        self.thinking = Some(task::spawn_blocking(move || {
+            let direction = player.get_next_move_with_deadline(&map, &player_id, deadline);
+            (player, direction)
+        }));
+        self.wait_until(deadline).await
+    }
+
+    async fn wait_until(&mut self, deadline: Instant) -> Option<Direction> {
+        let result = time::timeout_at(deadline.into(), self.thinking.as_mut()?).await.ok()?;
+        self.thinking = None;
+        Some(self.take_back(result))
+    }
+
+    // Waits for a move that is still running, however long it takes
+    async fn settle(&mut self) {
+        if let Some(thinking) = self.thinking.take() {
+            self.take_back(thinking.await);
+        }
+    }
+
+    fn take_back(&mut self, result: Result<(P, Direction), JoinError>) -> Direction {
+        let (player, direction) = result.unwrap_or_else(|error| match error.try_into_panic() {
+            Ok(payload) => panic::resume_unwind(payload),
+            // Blocking tasks are only cancelled when the runtime shuts down, which drops this future first
+            Err(error) => unreachable!("The move task was cancelled: {}", error),
+        });
+        let player = self.player.insert(player);
+        for call in mem::take(&mut self.queued) {
+            make_call(player, call);
+        }
+        direction
+    }
+}
+
+fn make_call<P: Player>(player: &mut P, call: Call) {
+    match call {
+        Call::Message(message) => notify(player, &message),
+        Call::Reconnect(attempt) => player.on_reconnect(attempt),
+        Call::Heartbeat(round_trip) => player.on_heartbeat(round_trip),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn get_config(move_margin_ms: u64) -> Config {
+        Config { move_margin_ms, ..Default::default() }
+    }
+
+    #[test]
+    fn move_margin_is_clamped_to_the_tick() {
+        assert_eq!(Duration::from_millis(200), time_to_move(&get_config(50), 250));
+        assert_eq!(Duration::from_millis(125), time_to_move(&get_config(250), 250));
+        assert_eq!(Duration::from_millis(50), time_to_move(&get_config(1000), 100));
+    }
+}
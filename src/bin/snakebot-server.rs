@@ -0,0 +1,68 @@
+#![deny(clippy::all)]
+use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version, value_t, Arg};
+use env_logger::Builder;
+use log::LevelFilter;
+use snakebot_rust::{
+    engine::GameOptions,
+    server::{Server, ServerConfig},
+};
+
+const DEFAULT_HOST: &str = "localhost";
+const DEFAULT_PORT: &str = "8080";
+const DEFAULT_TRAINING_BOTS: &str = "2";
+const DEFAULT_TOURNAMENT_PLAYERS: &str = "2";
+
+fn main() {
+    Builder::from_default_env().filter_module(crate_name!(), LevelFilter::Info).init();
+
+    let matches = app_from_crate!()
+        .arg(
+            Arg::with_name("host")
+                .short("h")
+                .long("host")
+                .help("The host to listen on")
+                .takes_value(true)
+                .default_value(DEFAULT_HOST),
+        )
+        .arg(
+            Arg::with_name("port")
+                .short("p")
+                .long("port")
+                .help("The port to listen on")
+                .takes_value(true)
+                .default_value(DEFAULT_PORT),
+        )
+        .arg(
+            Arg::with_name("training-bots")
+                .short("b")
+                .long("training-bots")
+                .help("The number of bots to play against in training games")
+                .takes_value(true)
+                .default_value(DEFAULT_TRAINING_BOTS),
+        )
+        .arg(
+            Arg::with_name("tournament-players")
+                .short("t")
+                .long("tournament-players")
+                .help("The number of players needed to start a tournament game")
+                .takes_value(true)
+                .default_value(DEFAULT_TOURNAMENT_PLAYERS),
+        )
+        .arg(Arg::with_name("seed").short("s").long("seed").help("Seed for reproducible games").takes_value(true))
+        .get_matches();
+
+    let seed = if matches.is_present("seed") {
+        Some(value_t!(matches, "seed", u64).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    };
+    let config = ServerConfig {
+        training_bots: value_t!(matches, "training-bots", usize).unwrap_or_else(|e| e.exit()),
+        tournament_players: value_t!(matches, "tournament-players", usize).unwrap_or_else(|e| e.exit()),
+        game_options: GameOptions { seed, ..Default::default() },
+    };
+    let address = format!("{}:{}", matches.value_of("host").unwrap(), matches.value_of("port").unwrap());
+
+    let server = Server::bind(&address, config).unwrap();
+    server.run().unwrap();
+}
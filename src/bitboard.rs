@@ -0,0 +1,470 @@
+use crate::{
+    types::{Direction, Map, Position},
+    utils::{Coordinate, Tile},
+};
+use std::ops::{BitAnd, BitOr, Not};
+
+const WORDS: usize = 32;
+// The largest map a bitboard can hold, the default 46 by 34 map fits with room to spare
+pub const MAX_TILES: usize = WORDS * 64;
+
+// A fixed size set of positions that is cheap to copy
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Bits([u64; WORDS]);
+
+impl Bits {
+    pub fn empty() -> Bits {
+        Bits([0; WORDS])
+    }
+
+    pub fn get(&self, position: Position) -> bool {
+        self.0[position as usize / 64] & (1 << (position as usize % 64)) != 0
+    }
+
+    pub fn set(&mut self, position: Position) {
+        self.0[position as usize / 64] |= 1 << (position as usize % 64);
+    }
+
+    pub fn clear(&mut self, position: Position) {
+        self.0[position as usize / 64] &= !(1 << (position as usize % 64));
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&word| word == 0)
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = Position> + '_ {
+        self.0.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64).filter(move |bit| word & (1 << bit) != 0).map(move |bit| (index * 64 + bit) as Position)
+        })
+    }
+
+    // Moves every position `n` steps towards higher positions
+    fn shift_up(&self, n: usize) -> Bits {
+        let (words, bits) = (n / 64, n % 64);
+        let mut shifted = Bits::empty();
+        for index in (words..WORDS).rev() {
+            let source = index - words;
+            shifted.0[index] = self.0[source] << bits;
+            if bits > 0 && source > 0 {
+                shifted.0[index] |= self.0[source - 1] >> (64 - bits);
+            }
+        }
+        shifted
+    }
+
+    // Moves every position `n` steps towards lower positions
+    fn shift_down(&self, n: usize) -> Bits {
+        let (words, bits) = (n / 64, n % 64);
+        let mut shifted = Bits::empty();
+        for index in 0..WORDS - words {
+            let source = index + words;
+            shifted.0[index] = self.0[source] >> bits;
+            if bits > 0 && source + 1 < WORDS {
+                shifted.0[index] |= self.0[source + 1] << (64 - bits);
+            }
+        }
+        shifted
+    }
+}
+
+impl BitAnd for Bits {
+    type Output = Bits;
+    fn bitand(mut self, rhs: Bits) -> Bits {
+        self.0.iter_mut().zip(rhs.0.iter()).for_each(|(word, other)| *word &= other);
+        self
+    }
+}
+
+impl BitOr for Bits {
+    type Output = Bits;
+    fn bitor(mut self, rhs: Bits) -> Bits {
+        self.0.iter_mut().zip(rhs.0.iter()).for_each(|(word, other)| *word |= other);
+        self
+    }
+}
+
+impl Not for Bits {
+    type Output = Bits;
+    fn not(mut self) -> Bits {
+        self.0.iter_mut().for_each(|word| *word = !*word);
+        self
+    }
+}
+
+impl std::fmt::Debug for Bits {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_set().entries(self.positions()).finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnakeBits {
+    pub occupancy: Bits,
+    pub head: Position,
+    pub tail: Position,
+    pub length: usize,
+    // Segments on the tail's tile besides the tail itself, like those of a snake that has not left its start yet
+    pub stacked: usize,
+}
+
+// Bit sets over the positions of a map. Every body part stores the direction to the next part towards the head
+// in two bit planes, so snakes can move without keeping their positions around.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bitboard {
+    width: i32,
+    height: i32,
+    inside: Bits,
+    not_first_column: Bits,
+    not_last_column: Bits,
+    pub obstacles: Bits,
+    pub food: Bits,
+    // Indexed like `snake_infos`, None for dead snakes
+    pub snakes: Vec<Option<SnakeBits>>,
+    link_low: Bits,
+    link_high: Bits,
+}
+
+impl Bitboard {
+    // None if the map has more than MAX_TILES tiles, or a snake has two segments on one tile anywhere but at its tail
+    pub fn from_map(map: &Map) -> Option<Bitboard> {
+        if (map.width * map.height) as usize > MAX_TILES {
+            return None;
+        }
+        let mut board = Bitboard {
+            width: map.width,
+            height: map.height,
+            inside: Bits::empty(),
+            not_first_column: Bits::empty(),
+            not_last_column: Bits::empty(),
+            obstacles: Bits::empty(),
+            food: Bits::empty(),
+            snakes: Vec::with_capacity(map.snake_infos.len()),
+            link_low: Bits::empty(),
+            link_high: Bits::empty(),
+        };
+        for position in 0..map.width * map.height {
+            board.inside.set(position);
+            if position % map.width != 0 {
+                board.not_first_column.set(position);
+            }
+            if position % map.width != map.width - 1 {
+                board.not_last_column.set(position);
+            }
+        }
+        map.obstacle_positions.iter().for_each(|&position| board.obstacles.set(position));
+        map.food_positions.iter().for_each(|&position| board.food.set(position));
+
+        for snake in &map.snake_infos {
+            let (head, tail) = match (snake.positions.first(), snake.positions.last()) {
+                (Some(&head), Some(&tail)) => (head, tail),
+                _ => {
+                    board.snakes.push(None);
+                    continue;
+                }
+            };
+            let stacked = snake.positions.iter().rev().take_while(|&&position| position == tail).count() - 1;
+            let unstacked = &snake.positions[..snake.positions.len() - stacked];
+            let mut occupancy = Bits::empty();
+            occupancy.set(head);
+            for pair in unstacked.windows(2) {
+                if pair[0] == pair[1] {
+                    return None;
+                }
+                occupancy.set(pair[1]);
+                board.set_link(pair[1], board.direction_between(pair[1], pair[0]));
+            }
+            board.snakes.push(Some(SnakeBits { occupancy, head, tail, length: snake.positions.len(), stacked }));
+        }
+        Some(board)
+    }
+
+    // The map with the positions from this board, names, ids and points are taken from `original`
+    pub fn to_map(&self, original: &Map) -> Map {
+        let mut map = original.clone();
+        map.obstacle_positions = self.obstacles.positions().collect();
+        map.food_positions = self.food.positions().collect();
+        for (snake_info, snake) in map.snake_infos.iter_mut().zip(self.snakes.iter()) {
+            snake_info.positions = snake.map_or_else(Vec::new, |snake| self.body(&snake));
+        }
+        map
+    }
+
+    // The tile view for debugging, with the snake infos taken from `map`. Overlapping cells are resolved like
+    // `Map::get_tile_at` does.
+    pub fn get_tile_at<'a>(&self, map: &'a Map, coordinate: Coordinate) -> Tile<'a> {
+        if !self.inside_map(coordinate) {
+            return Tile::Wall;
+        }
+        let position = coordinate.to_position(self.width);
+        if self.obstacles.get(position) {
+            return Tile::Obstacle { coordinate };
+        }
+        if self.food.get(position) {
+            return Tile::Food { coordinate };
+        }
+        let owner = self.snakes.iter().position(|snake| snake.is_some_and(|snake| snake.occupancy.get(position)));
+        match owner {
+            Some(owner) if self.snakes[owner].unwrap().head == position => {
+                Tile::SnakeHead { coordinate, snake: &map.snake_infos[owner] }
+            }
+            Some(owner) => Tile::SnakeBody { coordinate, snake: &map.snake_infos[owner] },
+            None => Tile::Empty { coordinate },
+        }
+    }
+
+    pub fn inside_map(&self, coordinate: Coordinate) -> bool {
+        let Coordinate(x, y) = coordinate;
+        x >= 0 && x < self.width && y >= 0 && y < self.height
+    }
+
+    pub fn occupied(&self) -> Bits {
+        self.snakes.iter().flatten().fold(self.obstacles, |occupied, snake| occupied | snake.occupancy)
+    }
+
+    pub fn free(&self) -> Bits {
+        self.inside & !self.occupied()
+    }
+
+    // The positions next to any of `bits`, within the map
+    pub fn neighbours(&self, bits: Bits) -> Bits {
+        let up = bits.shift_down(self.width as usize);
+        let down = bits.shift_up(self.width as usize);
+        let left = (bits & self.not_first_column).shift_down(1);
+        let right = (bits & self.not_last_column).shift_up(1);
+        (up | down | left | right) & self.inside
+    }
+
+    // The free positions reachable from `from`, which is not included unless it can be reached again
+    pub fn flood_fill(&self, from: Position) -> Bits {
+        let free = self.free();
+        let mut start = Bits::empty();
+        start.set(from);
+        let mut reached = self.neighbours(start) & free;
+        loop {
+            let next = reached | (self.neighbours(reached) & free);
+            if next == reached {
+                return reached;
+            }
+            reached = next;
+        }
+    }
+
+    // Moves the snake one step, dropping the tail unless it grows. Returns false and leaves the snake as it is
+    // if it is dead or would leave the map, otherwise whether the new head position was free after the tail moved.
+    pub fn move_snake(&mut self, index: usize, direction: Direction, grows: bool) -> bool {
+        let mut snake = match self.snakes[index] {
+            Some(snake) => snake,
+            None => return false,
+        };
+        let target = Coordinate::from_position(snake.head, self.width) + direction.to_movement_delta();
+        if !self.inside_map(target) {
+            return false;
+        }
+        let target = target.to_position(self.width);
+
+        self.set_link(snake.head, direction);
+        if grows {
+            snake.length += 1;
+        } else if snake.stacked > 0 {
+            snake.stacked -= 1;
+        } else {
+            snake.occupancy.clear(snake.tail);
+            if snake.length > 1 {
+                snake.tail = self.next_towards_head(snake.tail);
+            } else {
+                snake.tail = target;
+            }
+        }
+        let free = !self.occupied().get(target);
+        snake.occupancy.set(target);
+        snake.head = target;
+        self.snakes[index] = Some(snake);
+        free
+    }
+
+    pub fn remove_snake(&mut self, index: usize) {
+        self.snakes[index] = None;
+    }
+
+    fn body(&self, snake: &SnakeBits) -> Vec<Position> {
+        let mut positions = vec![snake.tail; snake.stacked + 1];
+        while positions.len() < snake.length {
+            positions.push(self.next_towards_head(*positions.last().unwrap()));
+        }
+        positions.reverse();
+        positions
+    }
+
+    fn next_towards_head(&self, position: Position) -> Position {
+        let direction = match (self.link_high.get(position), self.link_low.get(position)) {
+            (false, false) => Direction::Up,
+            (false, true) => Direction::Down,
+            (true, false) => Direction::Left,
+            (true, true) => Direction::Right,
+        };
+        (Coordinate::from_position(position, self.width) + direction.to_movement_delta()).to_position(self.width)
+    }
+
+    fn set_link(&mut self, position: Position, direction: Direction) {
+        let (high, low) = match direction {
+            Direction::Up => (false, false),
+            Direction::Down => (false, true),
+            Direction::Left => (true, false),
+            Direction::Right => (true, true),
+        };
+        if high {
+            self.link_high.set(position);
+        } else {
+            self.link_high.clear(position);
+        }
+        if low {
+            self.link_low.set(position);
+        } else {
+            self.link_low.clear(position);
+        }
+    }
+
+    fn direction_between(&self, from: Position, to: Position) -> Direction {
+        match to - from {
+            delta if delta == -self.width => Direction::Up,
+            delta if delta == self.width => Direction::Down,
+            -1 => Direction::Left,
+            _ => Direction::Right,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        board::Board,
+        utils::test::{get_map, get_pocket_map},
+    };
+
+    const MAP_WIDTH: i32 = 5;
+
+    fn get_test_map() -> Map {
+        get_pocket_map(&[("2", &[Coordinate(4, 3)])])
+    }
+
+    #[test]
+    fn bitboard_converts_back_to_map_and_tiles() {
+        let map = get_test_map();
+        let board = Bitboard::from_map(&map).unwrap();
+        let converted = board.to_map(&map);
+
+        assert_eq!(map.snake_infos, converted.snake_infos);
+        for y in -1..=map.height {
+            for x in -1..=map.width {
+                assert_eq!(map.get_tile_at(Coordinate(x, y)), board.get_tile_at(&map, Coordinate(x, y)));
+            }
+        }
+    }
+
+    #[test]
+    fn overlapping_cells_read_the_same_in_every_representation() {
+        // Food under the head of 1, an obstacle under the body of 2
+        let map = get_map(
+            MAP_WIDTH,
+            MAP_WIDTH,
+            &[("1", &[Coordinate(1, 1), Coordinate(1, 2)]), ("2", &[Coordinate(3, 1), Coordinate(3, 2)])],
+            &[Coordinate(1, 1)],
+            &[Coordinate(3, 2)],
+        );
+        let board = Board::new(&map);
+        let bitboard = Bitboard::from_map(&map).unwrap();
+
+        assert_eq!(Tile::Food { coordinate: Coordinate(1, 1) }, map.get_tile_at(Coordinate(1, 1)));
+        assert_eq!(Tile::Obstacle { coordinate: Coordinate(3, 2) }, map.get_tile_at(Coordinate(3, 2)));
+        for y in -1..=map.height {
+            for x in -1..=map.width {
+                let coordinate = Coordinate(x, y);
+                assert_eq!(map.get_tile_at(coordinate), board.get_tile_at(coordinate));
+                assert_eq!(map.get_tile_at(coordinate), bitboard.get_tile_at(&map, coordinate));
+            }
+        }
+    }
+
+    #[test]
+    fn neighbours_do_not_wrap_around_rows() {
+        let map = get_test_map();
+        let board = Bitboard::from_map(&map).unwrap();
+        let mut bits = Bits::empty();
+        bits.set(Coordinate(4, 1).to_position(MAP_WIDTH));
+
+        let neighbours: Vec<_> = board.neighbours(bits).positions().collect();
+        let expected: Vec<_> =
+            [Coordinate(4, 0), Coordinate(3, 1), Coordinate(4, 2)].iter().map(|c| c.to_position(MAP_WIDTH)).collect();
+        assert_eq!(expected, neighbours);
+    }
+
+    #[test]
+    fn flood_fill_matches_reachable_area() {
+        let map = get_test_map();
+        let board = Bitboard::from_map(&map).unwrap();
+        let from = Coordinate(1, 0);
+
+        let reached = board.flood_fill(from.to_position(MAP_WIDTH));
+        assert_eq!(map.reachable_area(from, &map.snake_infos[0]).tiles, reached.count());
+        assert!(reached.get(from.to_position(MAP_WIDTH)));
+        assert!(!reached.get(Coordinate(4, 3).to_position(MAP_WIDTH)));
+    }
+
+    #[test]
+    fn moved_snakes_keep_their_shape_in_copies() {
+        let map = get_test_map();
+        let board = Bitboard::from_map(&map).unwrap();
+        let mut next = board.clone();
+
+        assert!(next.move_snake(0, Direction::Right, false));
+        assert!(next.move_snake(1, Direction::Down, true));
+        assert!(!next.move_snake(1, Direction::Right, false));
+
+        let moved = next.to_map(&map);
+        let positions = |coordinates: &[Coordinate]| -> Vec<Position> {
+            coordinates.iter().map(|c| c.to_position(MAP_WIDTH)).collect()
+        };
+        assert_eq!(
+            positions(&[Coordinate(1, 0), Coordinate(0, 0), Coordinate(0, 1), Coordinate(0, 2), Coordinate(1, 2)]),
+            moved.snake_infos[0].positions
+        );
+        assert_eq!(positions(&[Coordinate(4, 4), Coordinate(4, 3)]), moved.snake_infos[1].positions);
+        assert_eq!(map.snake_infos, board.to_map(&map).snake_infos);
+    }
+
+    #[test]
+    fn stacked_snakes_convert_back_and_unfold() {
+        let stacked = [Coordinate(2, 4), Coordinate(2, 4), Coordinate(2, 4)];
+        let map = get_map(MAP_WIDTH, MAP_WIDTH, &[("1", &stacked)], &[], &[]);
+        let mut board = Bitboard::from_map(&map).unwrap();
+
+        assert_eq!(map.snake_infos, board.to_map(&map).snake_infos);
+        for y in 0..map.height {
+            for x in 0..map.width {
+                assert_eq!(map.get_tile_at(Coordinate(x, y)), board.get_tile_at(&map, Coordinate(x, y)));
+            }
+        }
+
+        let positions = |coordinates: &[Coordinate]| -> Vec<Position> {
+            coordinates.iter().map(|c| c.to_position(MAP_WIDTH)).collect()
+        };
+        assert!(board.move_snake(0, Direction::Up, false));
+        assert_eq!(
+            positions(&[Coordinate(2, 3), Coordinate(2, 4), Coordinate(2, 4)]),
+            board.to_map(&map).snake_infos[0].positions
+        );
+        assert!(board.move_snake(0, Direction::Up, false));
+        assert!(board.move_snake(0, Direction::Right, false));
+        assert_eq!(
+            positions(&[Coordinate(3, 2), Coordinate(2, 2), Coordinate(2, 3)]),
+            board.to_map(&map).snake_infos[0].positions
+        );
+        assert!(!board.occupied().get(Coordinate(2, 4).to_position(MAP_WIDTH)));
+    }
+}
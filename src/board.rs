@@ -0,0 +1,184 @@
+use crate::{
+    types::{Direction, Map, SnakeInfo},
+    utils::{Coordinate, Tile},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cell {
+    Wall,
+    Empty,
+    Food,
+    Obstacle,
+    // The snake at index `owner` in `snake_infos`, age 0 is the head and grows towards the tail
+    Snake { owner: usize, age: usize },
+}
+
+// A grid built once from a Map for constant time lookups, indexed by position
+#[derive(Debug, Clone)]
+pub struct Board<'a> {
+    map: &'a Map,
+    cells: Vec<Cell>,
+}
+
+impl<'a> Board<'a> {
+    pub fn new(map: &'a Map) -> Board<'a> {
+        let mut cells = vec![Cell::Empty; (map.width * map.height) as usize];
+        // Later writes win, so this goes from the lowest precedence in `Map::get_tile_at` to the highest: later snakes
+        // before earlier ones, a snake's tail before its head for the segments stacked at the start of a game, then
+        // food and obstacles
+        for (owner, snake) in map.snake_infos.iter().enumerate().rev() {
+            for (age, &position) in snake.positions.iter().enumerate().rev() {
+                cells[position as usize] = Cell::Snake { owner, age };
+            }
+        }
+        for &position in &map.food_positions {
+            cells[position as usize] = Cell::Food;
+        }
+        for &position in &map.obstacle_positions {
+            cells[position as usize] = Cell::Obstacle;
+        }
+        Board { map, cells }
+    }
+
+    pub fn map(&self) -> &'a Map {
+        self.map
+    }
+
+    pub fn cell_at(&self, coordinate: Coordinate) -> Cell {
+        if self.map.inside_map(coordinate) {
+            self.cells[coordinate.to_position(self.map.width) as usize]
+        } else {
+            Cell::Wall
+        }
+    }
+
+    pub fn snake_at(&self, coordinate: Coordinate) -> Option<&'a SnakeInfo> {
+        match self.cell_at(coordinate) {
+            Cell::Snake { owner, .. } => Some(&self.map.snake_infos[owner]),
+            _ => None,
+        }
+    }
+
+    // The number of moves until the body part at `coordinate` has left it, if no snake grows
+    pub fn ticks_until_free(&self, coordinate: Coordinate) -> Option<usize> {
+        match self.cell_at(coordinate) {
+            Cell::Wall | Cell::Obstacle => None,
+            Cell::Empty | Cell::Food => Some(0),
+            Cell::Snake { owner, age } => Some(self.map.snake_infos[owner].positions.len() - age),
+        }
+    }
+
+    pub fn get_tile_at(&self, coordinate: Coordinate) -> Tile<'a> {
+        match self.cell_at(coordinate) {
+            Cell::Wall => Tile::Wall,
+            Cell::Empty => Tile::Empty { coordinate },
+            Cell::Food => Tile::Food { coordinate },
+            Cell::Obstacle => Tile::Obstacle { coordinate },
+            Cell::Snake { owner, age: 0 } => Tile::SnakeHead { coordinate, snake: &self.map.snake_infos[owner] },
+            Cell::Snake { owner, .. } => Tile::SnakeBody { coordinate, snake: &self.map.snake_infos[owner] },
+        }
+    }
+
+    pub fn is_tile_available_for_movement(&self, coordinate: Coordinate) -> bool {
+        matches!(self.cell_at(coordinate), Cell::Empty | Cell::Food)
+    }
+
+    pub fn can_snake_move_in_direction(&self, snake: &SnakeInfo, direction: Direction) -> bool {
+        let head = Coordinate::from_position(*snake.positions.first().unwrap(), self.map.width);
+        self.is_tile_available_for_movement(head + direction.to_movement_delta())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::{test::get_map, DIRECTIONS};
+
+    const MAP_WIDTH: i32 = 4;
+
+    // The map used for testing, 1 and 2 represents the snakes
+    //yx0123
+    //0 F111
+    //1   #1
+    //2 2  1
+    fn get_test_map() -> Map {
+        get_map(
+            MAP_WIDTH,
+            3,
+            &[
+                ("1", &[Coordinate(3, 2), Coordinate(3, 1), Coordinate(3, 0), Coordinate(2, 0), Coordinate(1, 0)]),
+                ("2", &[Coordinate(0, 2)]),
+            ],
+            &[Coordinate(0, 0)],
+            &[Coordinate(2, 1)],
+        )
+    }
+
+    #[test]
+    fn board_agrees_with_map() {
+        let map = get_test_map();
+        let board = Board::new(&map);
+
+        for y in -1..=map.height {
+            for x in -1..=map.width {
+                let coordinate = Coordinate(x, y);
+                assert_eq!(map.get_tile_at(coordinate), board.get_tile_at(coordinate));
+                assert_eq!(
+                    map.is_tile_available_for_movement(coordinate),
+                    board.is_tile_available_for_movement(coordinate)
+                );
+            }
+        }
+        for snake in &map.snake_infos {
+            for &direction in &DIRECTIONS {
+                assert_eq!(
+                    map.can_snake_move_in_direction(snake, direction),
+                    board.can_snake_move_in_direction(snake, direction)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cells_know_owner_and_age() {
+        let map = get_test_map();
+        let board = Board::new(&map);
+
+        assert_eq!(Cell::Snake { owner: 0, age: 0 }, board.cell_at(Coordinate(3, 2)));
+        assert_eq!(Cell::Snake { owner: 0, age: 4 }, board.cell_at(Coordinate(1, 0)));
+        assert_eq!(Cell::Snake { owner: 1, age: 0 }, board.cell_at(Coordinate(0, 2)));
+        assert_eq!(Some("2"), board.snake_at(Coordinate(0, 2)).map(|snake| snake.id.as_str()));
+        assert_eq!(Some(1), board.ticks_until_free(Coordinate(1, 0)));
+        assert_eq!(Some(0), board.ticks_until_free(Coordinate(0, 0)));
+        assert_eq!(Some(4), board.ticks_until_free(Coordinate(3, 1)));
+        assert_eq!(None, board.ticks_until_free(Coordinate(2, 1)));
+        assert_eq!(Cell::Wall, board.cell_at(Coordinate(4, 0)));
+    }
+
+    fn assert_board_agrees_with_map(map: &Map) {
+        let board = Board::new(map);
+        for y in 0..map.height {
+            for x in 0..map.width {
+                assert_eq!(map.get_tile_at(Coordinate(x, y)), board.get_tile_at(Coordinate(x, y)));
+            }
+        }
+    }
+
+    #[test]
+    fn stacked_segments_are_the_head() {
+        let map = get_map(MAP_WIDTH, 3, &[("1", &[Coordinate(1, 1), Coordinate(1, 1), Coordinate(1, 1)])], &[], &[]);
+        let board = Board::new(&map);
+
+        assert_eq!(Cell::Snake { owner: 0, age: 0 }, board.cell_at(Coordinate(1, 1)));
+        assert_eq!(Some(3), board.ticks_until_free(Coordinate(1, 1)));
+        assert_board_agrees_with_map(&map);
+    }
+
+    #[test]
+    fn food_under_a_snake_is_food() {
+        let map = get_map(MAP_WIDTH, 3, &[("1", &[Coordinate(1, 1), Coordinate(2, 1)])], &[Coordinate(2, 1)], &[]);
+
+        assert_eq!(Cell::Food, Board::new(&map).cell_at(Coordinate(2, 1)));
+        assert_board_agrees_with_map(&map);
+    }
+}
@@ -0,0 +1,44 @@
+use crate::{
+    client::Player,
+    types::{Direction, GameSettings, Map},
+    utils::DIRECTIONS,
+};
+
+// A simple opponent that keeps going straight until it has to turn
+#[derive(Debug, Clone)]
+pub struct SafeBot {
+    direction: Direction,
+}
+
+impl SafeBot {
+    pub fn new() -> SafeBot {
+        SafeBot { direction: Direction::Down }
+    }
+}
+
+impl Default for SafeBot {
+    fn default() -> SafeBot {
+        SafeBot::new()
+    }
+}
+
+impl Player for SafeBot {
+    fn get_next_move(&mut self, map: &Map, player_id: &str) -> Direction {
+        let snake = match map.get_snake_by_id(player_id) {
+            Some(snake) if !snake.positions.is_empty() => snake,
+            _ => return self.direction,
+        };
+
+        let current = self.direction;
+        if let Some(&direction) =
+            Some(&current).into_iter().chain(DIRECTIONS.iter()).find(|&&d| map.can_snake_move_in_direction(snake, d))
+        {
+            self.direction = direction;
+        }
+        self.direction
+    }
+
+    fn on_game_starting(&mut self, _: &GameSettings) {
+        self.direction = Direction::Down;
+    }
+}
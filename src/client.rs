@@ -1,23 +1,79 @@
-use crate::types::{Direction, GameMode, InboundMessage, Map, OutboundMessage};
-use clap::crate_version;
-use log::{debug, info};
-use rustc_version::version;
-use serde_json;
-use std::mem;
-use target_info::Target;
-use ws;
-
-const HEARTBEAT_TOKEN: ws::util::Token = ws::util::Token(1337);
-const HEARTBEAT_INTERVAL: u64 = 10_000;
+use crate::{
+  async_client::AsyncClient,
+  types::{
+    DeathReason, Direction, GameMode, GameResult, GameSettings, InboundMessage, Map, PlayerNameInvalidReason,
+    PlayerRank,
+  },
+  utils::DIRECTIONS,
+};
+use std::{
+  cmp,
+  error::Error,
+  fmt, io,
+  panic::resume_unwind,
+  sync::mpsc,
+  thread,
+  time::{Duration, Instant},
+};
+use tokio::runtime;
+use tokio_tungstenite::tungstenite;
+pub use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 
 #[derive(Debug)]
 pub enum ClientError {
-  WebSocket(ws::Error),
+  WebSocket(tungstenite::Error),
+  // A message to the server could not be encoded. Inbound messages that can not be read are logged and skipped instead.
   SerdeJson(serde_json::Error),
+  // The replay directory or the runtime could not be set up
+  Io(io::Error),
+  // `tls_ca_file` could not be read or holds no usable certificate
+  InvalidTlsCaFile { file: String, cause: Box<dyn Error + Send + Sync> },
+  InvalidPlayerName(PlayerNameInvalidReason),
+  // The server stopped answering heartbeats
+  HeartbeatTimeout,
+  // The server closed the connection with something other than a normal close
+  Closed { code: CloseCode, reason: String },
+  // The connection was lost and could not be restored, `cause` is why the last attempt failed
+  Disconnected { attempts: u32, cause: Option<Box<ClientError>> },
 }
 
-impl From<ws::Error> for ClientError {
-  fn from(error: ws::Error) -> ClientError {
+impl fmt::Display for ClientError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      // Saying "IO error" adds nothing to a refused connection
+      ClientError::WebSocket(tungstenite::Error::Io(error)) => write!(f, "{}", error),
+      ClientError::WebSocket(error) => write!(f, "WebSocket error: {}", error),
+      ClientError::SerdeJson(error) => write!(f, "could not encode message: {}", error),
+      ClientError::Io(error) => write!(f, "I/O error: {}", error),
+      ClientError::InvalidTlsCaFile { file, cause } => write!(f, "could not use tls_ca_file {:?}: {}", file, cause),
+      ClientError::InvalidPlayerName(reason) => write!(f, "the server rejected the player name: {:?}", reason),
+      ClientError::HeartbeatTimeout => write!(f, "the server stopped answering heartbeats"),
+      ClientError::Closed { code, reason } => write!(f, "the server closed the connection ({:?}): {}", code, reason),
+      ClientError::Disconnected { attempts, cause: Some(cause) } => {
+        write!(f, "connection lost, gave up after {} reconnect attempts: {}", attempts, cause)
+      }
+      ClientError::Disconnected { attempts, cause: None } => {
+        write!(f, "connection lost, gave up after {} reconnect attempts", attempts)
+      }
+    }
+  }
+}
+
+impl Error for ClientError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ClientError::WebSocket(error) => Some(error),
+      ClientError::SerdeJson(error) => Some(error),
+      ClientError::Io(error) => Some(error),
+      ClientError::InvalidTlsCaFile { cause, .. } => Some(cause.as_ref()),
+      ClientError::Disconnected { cause: Some(cause), .. } => Some(cause.as_ref()),
+      _ => None,
+    }
+  }
+}
+
+impl From<tungstenite::Error> for ClientError {
+  fn from(error: tungstenite::Error) -> ClientError {
     ClientError::WebSocket(error)
   }
 }
@@ -28,158 +84,692 @@ impl From<serde_json::Error> for ClientError {
   }
 }
 
+impl From<io::Error> for ClientError {
+  fn from(error: io::Error) -> ClientError {
+    ClientError::Io(error)
+  }
+}
+
 pub trait Player {
   fn get_next_move(&mut self, map: &Map, player_id: &str) -> Direction;
+  // Strategies that can think for a variable amount of time should answer before the deadline
+  fn get_next_move_with_deadline(&mut self, map: &Map, player_id: &str, _deadline: Instant) -> Direction {
+    self.get_next_move(map, player_id)
+  }
   fn on_message(&mut self, _: &InboundMessage) {}
+  fn on_reconnect(&mut self, _attempt: u32) {}
+  // The time from sending a heartbeat until the answer arrived, a rough measure of the network delay
+  fn on_heartbeat(&mut self, _round_trip: Duration) {}
+  fn on_registered(&mut self, _player_id: &str, _game_mode: GameMode) {}
+  fn on_game_starting(&mut self, _game_settings: &GameSettings) {}
+  fn on_snake_dead(&mut self, _player_id: &str, _death_reason: DeathReason) {}
+  fn on_game_ended(&mut self, _player_winner_id: &str, _map: &Map) {}
+  fn on_game_result(&mut self, _player_ranks: &[PlayerRank]) {}
+  fn on_tournament_ended(&mut self, _player_winner_id: &str, _game_result: &[GameResult]) {}
 }
 
+// Passes a message to the player, first as is and then to the matching lifecycle hook
+pub fn notify<P: Player + ?Sized>(player: &mut P, message: &InboundMessage) {
+  player.on_message(message);
+
+  match message {
+    InboundMessage::PlayerRegistered { receiving_player_id, game_mode, .. } => {
+      player.on_registered(receiving_player_id, *game_mode)
+    }
+    InboundMessage::GameStarting { game_settings, .. } => player.on_game_starting(game_settings),
+    InboundMessage::SnakeDead { player_id, death_reason, .. } => player.on_snake_dead(player_id, *death_reason),
+    InboundMessage::GameEnded { player_winner_id, map, .. } => player.on_game_ended(player_winner_id, map),
+    InboundMessage::GameResult { player_ranks, .. } => player.on_game_result(player_ranks),
+    InboundMessage::TournamentEnded { player_winner_id, game_result, .. } => {
+      player.on_tournament_ended(player_winner_id, game_result)
+    }
+    _ => {}
+  }
+}
+
+pub const DEFAULT_HOST: &str = "snake.cygni.se";
+pub const DEFAULT_PORT: i32 = 80;
+pub const DEFAULT_VENUE: &str = "training";
+pub const DEFAULT_SNAKE_NAME: &str = "default-rust-snake-name";
+pub const DEFAULT_RECONNECT_ATTEMPTS: u32 = 5;
+pub const DEFAULT_RECONNECT_DELAY_MS: u64 = 500;
+pub const DEFAULT_MAX_RECONNECT_DELAY_MS: u64 = 30_000;
+pub const DEFAULT_GAMES: u32 = 1;
+pub const DEFAULT_MOVE_MARGIN_MS: u64 = 50;
+pub const DEFAULT_HEARTBEAT_INTERVAL_MS: u64 = 10_000;
+pub const DEFAULT_HEARTBEAT_TIMEOUT_MS: u64 = 30_000;
+
+// Fill in the fields you care about with `Config { snake_name, ..Default::default() }`, so new fields do not break
+// the build
 #[derive(Clone, Debug)]
 pub struct Config {
   pub host: String,
   pub port: i32,
   pub venue: String,
   pub snake_name: String,
+  pub reconnect_attempts: u32,
+  pub reconnect_delay_ms: u64,
+  pub max_reconnect_delay_ms: u64,
+  // The number of training games to play back-to-back, or None to keep playing forever
+  pub games: Option<u32>,
+  // How long before the end of a tick to send a fallback move if the player has not answered
+  pub move_margin_ms: u64,
+  // Where to write a replay of every game, nothing is recorded if None
+  pub replay_directory: Option<String>,
+  pub heartbeat_interval_ms: u64,
+  // How long to wait for a heartbeat response before dropping the connection
+  pub heartbeat_timeout_ms: u64,
+  // A full ws:// or wss:// URL to connect to instead of the one made from host, port and venue
+  pub url: Option<String>,
+  // A PEM file with certificates to trust besides the system ones, like that of a self-signed proxy
+  pub tls_ca_file: Option<String>,
 }
 
-pub struct Client<P: Player> {
-  player: P,
-  config: Config,
-  ws: ws::Sender,
-  player_id: Option<String>,
-  timeout: Option<ws::util::Timeout>,
+impl Default for Config {
+  fn default() -> Config {
+    Config {
+      host: DEFAULT_HOST.to_string(),
+      port: DEFAULT_PORT,
+      venue: DEFAULT_VENUE.to_string(),
+      snake_name: DEFAULT_SNAKE_NAME.to_string(),
+      reconnect_attempts: DEFAULT_RECONNECT_ATTEMPTS,
+      reconnect_delay_ms: DEFAULT_RECONNECT_DELAY_MS,
+      max_reconnect_delay_ms: DEFAULT_MAX_RECONNECT_DELAY_MS,
+      games: Some(DEFAULT_GAMES),
+      move_margin_ms: DEFAULT_MOVE_MARGIN_MS,
+      replay_directory: None,
+      heartbeat_interval_ms: DEFAULT_HEARTBEAT_INTERVAL_MS,
+      heartbeat_timeout_ms: DEFAULT_HEARTBEAT_TIMEOUT_MS,
+      url: None,
+      tls_ca_file: None,
+    }
+  }
 }
 
-impl<P: Player> Client<P> {
-  pub fn connect<F: Fn() -> P>(config: Config, create_player: F) -> ws::Result<()> {
-    let connection_url = format!("ws://{}:{}/{}", &config.host, &config.port, &config.venue);
-    info!("Connecting to {:?}", connection_url);
+impl Config {
+  pub fn connection_url(&self) -> String {
+    match self.url {
+      Some(ref url) => url.clone(),
+      None => format!("ws://{}:{}/{}", self.host, self.port, self.venue),
+    }
+  }
 
-    ws::connect(connection_url, |ws| Client {
-      player: create_player(),
-      config: config.clone(),
-      ws,
-      player_id: None,
-      timeout: None,
-    })
+  // Exponential backoff, doubling the delay for every failed attempt
+  pub fn reconnect_delay(&self, attempt: u32) -> Duration {
+    let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+    Duration::from_millis(cmp::min(self.reconnect_delay_ms.saturating_mul(factor), self.max_reconnect_delay_ms))
   }
+}
+
+#[derive(Clone, Debug)]
+pub struct GameOutcome {
+  pub game_id: String,
+  pub game_tick: u32,
+  pub player_winner_id: String,
+  pub won: bool,
+  pub points: i32,
+  pub missed_deadlines: u32,
+}
+
+// The first direction that does not kill the snake right away
+pub(crate) fn fallback_direction(map: &Map, player_id: &str) -> Direction {
+  map
+    .get_snake_by_id(player_id)
+    .filter(|snake| !snake.positions.is_empty())
+    .and_then(|snake| DIRECTIONS.iter().cloned().find(|&direction| map.can_snake_move_in_direction(snake, direction)))
+    .unwrap_or(Direction::Down)
+}
 
-  fn send_message(&self, message: OutboundMessage) -> ws::Result<()> {
-    debug!("Sending message: {:?}", message);
-    let json_string = serde_json::to_string(&message).map_err(Box::new)?;
-    self.ws.send(json_string)
+// Plays from the calling thread, which keeps the player, so unlike with AsyncClient it does not have to be Send. The
+// connection runs on a runtime of its own and asks this thread for moves.
+pub struct Client;
+
+impl Client {
+  pub fn connect<P: Player, F: FnOnce() -> P>(
+    config: Config,
+    create_player: F,
+  ) -> Result<Vec<GameOutcome>, ClientError> {
+    let mut player = create_player();
+    let runtime = runtime::Builder::new_current_thread().enable_all().build()?;
+    let (calls, requests) = mpsc::channel();
+    let session = thread::spawn(move || runtime.block_on(AsyncClient::connect(config, || RemotePlayer { calls })));
+
+    // Ends when the session is over and the remote player is dropped
+    for call in requests {
+      match call {
+        Call::Message(message) => notify(&mut player, &message),
+        Call::Reconnect(attempt) => player.on_reconnect(attempt),
+        Call::Heartbeat(round_trip) => player.on_heartbeat(round_trip),
+        Call::Move { map, player_id, deadline, reply } => {
+          let direction = match deadline {
+            Some(deadline) => player.get_next_move_with_deadline(&map, &player_id, deadline),
+            None => player.get_next_move(&map, &player_id),
+          };
+          // Nobody is waiting if the deadline has passed
+          let _ = reply.send(direction);
+        }
+      }
+    }
+    session.join().unwrap_or_else(|panic| resume_unwind(panic))
   }
 }
 
-impl<P: Player> ws::Handler for Client<P> {
-  fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
-    info!("WebSocket opened");
+enum Call {
+  Message(InboundMessage),
+  Reconnect(u32),
+  Heartbeat(Duration),
+  Move { map: Map, player_id: String, deadline: Option<Instant>, reply: mpsc::Sender<Direction> },
+}
 
-    self.send_message(OutboundMessage::ClientInfo {
-      language: "Rust",
-      language_version: &version().unwrap().to_string(),
-      operating_system: Target::os(),
-      operating_system_version: "???",
-      client_version: crate_version!(),
-    })?;
+// Stands in for the player on the runtime and forwards everything to the thread that has the real one. The lifecycle
+// hooks are left out since `notify` calls them there.
+struct RemotePlayer {
+  calls: mpsc::Sender<Call>,
+}
 
-    self.send_message(OutboundMessage::RegisterPlayer {
-      player_name: &self.config.snake_name,
-      game_settings: Default::default(),
-    })?;
+impl RemotePlayer {
+  fn call(&self, call: Call) {
+    // The calling thread only stops listening if the player panicked, which ends the session soon enough
+    let _ = self.calls.send(call);
+  }
 
-    Ok(())
+  fn ask(&self, map: &Map, player_id: &str, deadline: Option<Instant>) -> Direction {
+    let (reply, answer) = mpsc::channel();
+    self.call(Call::Move { map: map.clone(), player_id: player_id.to_string(), deadline, reply });
+    // Only the move task waits here, the session holds back other calls until it has this player back
+    answer.recv().unwrap_or_else(|_| fallback_direction(map, player_id))
   }
+}
 
-  fn on_timeout(&mut self, token: ws::util::Token) -> ws::Result<()> {
-    if token == HEARTBEAT_TOKEN {
-      self.ws.timeout(HEARTBEAT_INTERVAL, HEARTBEAT_TOKEN)?;
-      if let Some(ref player_id) = self.player_id {
-        self.send_message(OutboundMessage::HeartBeatRequest { receiving_player_id: player_id })?;
-      }
+impl Player for RemotePlayer {
+  fn get_next_move(&mut self, map: &Map, player_id: &str) -> Direction {
+    self.ask(map, player_id, None)
+  }
+
+  fn get_next_move_with_deadline(&mut self, map: &Map, player_id: &str, deadline: Instant) -> Direction {
+    self.ask(map, player_id, Some(deadline))
+  }
+
+  fn on_message(&mut self, message: &InboundMessage) {
+    self.call(Call::Message(message.clone()))
+  }
+
+  fn on_reconnect(&mut self, attempt: u32) {
+    self.call(Call::Reconnect(attempt))
+  }
+
+  fn on_heartbeat(&mut self, round_trip: Duration) {
+    self.call(Call::Heartbeat(round_trip))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use serde_json::json;
+  use std::{
+    cell::RefCell,
+    net::TcpListener,
+    rc::Rc,
+    sync::{
+      atomic::{AtomicUsize, Ordering},
+      Arc,
+    },
+  };
+
+  #[derive(Clone, Default)]
+  struct ReconnectingPlayer {
+    reconnects: Rc<RefCell<Vec<u32>>>,
+    round_trips: Rc<RefCell<Vec<Duration>>>,
+  }
+
+  impl Player for ReconnectingPlayer {
+    fn get_next_move(&mut self, _: &Map, _: &str) -> Direction {
+      Direction::Down
+    }
+
+    fn on_reconnect(&mut self, attempt: u32) {
+      self.reconnects.borrow_mut().push(attempt);
+    }
+
+    fn on_heartbeat(&mut self, round_trip: Duration) {
+      self.round_trips.borrow_mut().push(round_trip);
     }
-    Ok(())
   }
 
-  fn on_new_timeout(&mut self, event: ws::util::Token, timeout: ws::util::Timeout) -> ws::Result<()> {
-    if event == HEARTBEAT_TOKEN {
-      // Replace the current timeout with the new one
-      let prev_timeout_option = mem::replace(&mut self.timeout, Some(timeout));
+  // Serves on a free port and returns it with a handle to shut the server down
+  fn start_server<F>(factory: F) -> (u16, ws::Sender)
+  where
+    F: ws::Factory + Send + 'static,
+    F::Handler: Send,
+  {
+    start_server_with(ws::Settings::default(), factory)
+  }
 
-      if let Some(prev_timeout) = prev_timeout_option {
-        self.ws.cancel(prev_timeout)?;
+  fn start_server_with<F>(settings: ws::Settings, factory: F) -> (u16, ws::Sender)
+  where
+    F: ws::Factory + Send + 'static,
+    F::Handler: Send,
+  {
+    let server = ws::Builder::new().with_settings(settings).build(factory).unwrap().bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let broadcaster = server.broadcaster();
+    thread::spawn(move || server.run().unwrap());
+    (port, broadcaster)
+  }
+
+  const REGISTERED: &str = r#"{"type":"se.cygni.snake.api.response.PlayerRegistered","receivingPlayerId":"me",
+                               "name":"me","gameId":"","gameMode":"TOURNAMENT"}"#;
+
+  // A server that registers the player and answers heartbeats if `answer_heartbeats`, closing after the first one
+  fn start_heartbeat_server(answer_heartbeats: bool) -> (u16, ws::Sender) {
+    start_server(move |out: ws::Sender| {
+      move |message: ws::Message| {
+        let text = message.into_text()?;
+        if text.contains("RegisterPlayer") {
+          out.send(REGISTERED)?;
+        } else if text.contains("HeartBeatRequest") && answer_heartbeats {
+          out.send(r#"{"type":"se.cygni.snake.api.response.HeartBeatResponse","receivingPlayerId":"me"}"#)?;
+          out.close(ws::CloseCode::Normal)?;
+        }
+        Ok(())
       }
+    })
+  }
+
+  fn get_config(port: u16, reconnect_attempts: u32) -> Config {
+    Config {
+      host: "127.0.0.1".to_string(),
+      port: i32::from(port),
+      snake_name: "reconnecting".to_string(),
+      reconnect_attempts,
+      reconnect_delay_ms: 10,
+      max_reconnect_delay_ms: 20,
+      ..Default::default()
     }
-    Ok(())
   }
 
-  fn on_close(&mut self, code: ws::CloseCode, reason: &str) {
-    info!("WebSocket closed with code {:?} and reason: {}", code, reason);
+  #[test]
+  fn reconnect_delay_backs_off_exponentially() {
+    let config = Config { reconnect_delay_ms: 100, max_reconnect_delay_ms: 1000, ..get_config(80, 5) };
 
-    if let Some(timeout) = self.timeout.take() {
-      self.ws.cancel(timeout).unwrap();
-    }
+    assert_eq!(Duration::from_millis(100), config.reconnect_delay(1));
+    assert_eq!(Duration::from_millis(200), config.reconnect_delay(2));
+    assert_eq!(Duration::from_millis(800), config.reconnect_delay(4));
+    assert_eq!(Duration::from_millis(1000), config.reconnect_delay(5));
+    assert_eq!(Duration::from_millis(1000), config.reconnect_delay(64));
   }
 
-  fn on_message(&mut self, message: ws::Message) -> ws::Result<()> {
-    let text = message.into_text()?;
-    let message = serde_json::from_str::<InboundMessage>(&text).map_err(Box::new)?;
-    debug!("Received message: {:?}", message);
+  #[test]
+  fn client_reconnects_after_connection_is_lost() {
+    let connections = Arc::new(AtomicUsize::new(0));
+    let (port, broadcaster) = start_server(move |out: ws::Sender| {
+      let connection = connections.fetch_add(1, Ordering::SeqCst);
+      move |message: ws::Message| {
+        if message.into_text()?.contains("RegisterPlayer") {
+          let code = if connection == 0 { ws::CloseCode::Away } else { ws::CloseCode::Normal };
+          out.close(code)?;
+        }
+        Ok(())
+      }
+    });
+
+    let player = ReconnectingPlayer::default();
+    Client::connect(get_config(port, 3), || player.clone()).unwrap();
 
-    self.player.on_message(&message);
+    assert_eq!(vec![1], *player.reconnects.borrow());
+    broadcaster.shutdown().unwrap();
+  }
 
-    match message {
-      InboundMessage::PlayerRegistered { name, game_mode, receiving_player_id, .. } => {
-        info!("Successfully registered player {}", name);
-        if game_mode == GameMode::Training {
-          self.send_message(OutboundMessage::StartGame)?;
+  #[test]
+  fn connections_dropped_before_registering_count_as_attempts() {
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counted = connections.clone();
+    let (port, broadcaster) = start_server(move |out: ws::Sender| {
+      counted.fetch_add(1, Ordering::SeqCst);
+      move |message: ws::Message| {
+        if message.into_text()?.contains("RegisterPlayer") {
+          out.close(ws::CloseCode::Away)?;
         }
-        self.player_id = Some(receiving_player_id);
-        self.ws.timeout(HEARTBEAT_INTERVAL, HEARTBEAT_TOKEN)?;
+        Ok(())
       }
+    });
+
+    let player = ReconnectingPlayer::default();
+    match Client::connect(get_config(port, 2), || player.clone()) {
+      Err(ClientError::Disconnected { attempts: 2, cause: Some(_) }) => {}
+      other => panic!("Expected to give up, got {:?}", other.map(|outcomes| outcomes.len())),
+    }
+    assert_eq!(3, connections.load(Ordering::SeqCst));
+    assert_eq!(vec![1, 2], *player.reconnects.borrow());
+    broadcaster.shutdown().unwrap();
+  }
 
-      InboundMessage::InvalidPlayerName { .. } => {
-        info!("Player name invalid.");
+  #[test]
+  fn unreadable_messages_do_not_drop_the_connection() {
+    let (port, broadcaster) = start_server(|out: ws::Sender| {
+      move |message: ws::Message| {
+        if message.into_text()?.contains("RegisterPlayer") {
+          out.send(r#"{"type":"se.cygni.snake.api.event.ArenaUpdateEvent","arenaName":"arena"}"#)?;
+          out.send(r#"{"type":"se.cygni.snake.api.event.MapUpdateEvent","gameTick":"soon"}"#)?;
+          out.send("not json")?;
+          out.close(ws::CloseCode::Normal)?;
+        }
+        Ok(())
       }
+    });
+
+    let player = ReconnectingPlayer::default();
+    Client::connect(get_config(port, 3), || player.clone()).unwrap();
 
-      InboundMessage::GameStarting { .. } => {
-        info!("All snakes are ready to rock. Game is starting.");
+    assert!(player.reconnects.borrow().is_empty());
+    broadcaster.shutdown().unwrap();
+  }
+
+  #[test]
+  fn client_gives_up_after_reconnect_attempts() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let player = ReconnectingPlayer::default();
+
+    match Client::connect(get_config(port, 2), || player.clone()) {
+      Err(ClientError::Disconnected { attempts: 2, cause: Some(_) }) => {}
+      other => panic!("Expected to give up with a cause, got {:?}", other.map(|outcomes| outcomes.len())),
+    }
+    assert!(player.reconnects.borrow().is_empty());
+  }
+
+  #[test]
+  fn heartbeat_round_trip_is_reported() {
+    let (port, broadcaster) = start_heartbeat_server(true);
+    let config = Config { heartbeat_interval_ms: 20, ..get_config(port, 0) };
+
+    let player = ReconnectingPlayer::default();
+    Client::connect(config, || player.clone()).unwrap();
+
+    assert_eq!(1, player.round_trips.borrow().len());
+    broadcaster.shutdown().unwrap();
+  }
+
+  #[test]
+  fn connection_is_dropped_when_heartbeats_stop() {
+    let (port, broadcaster) = start_heartbeat_server(false);
+    let config = Config { heartbeat_interval_ms: 20, heartbeat_timeout_ms: 50, ..get_config(port, 0) };
+
+    let player = ReconnectingPlayer::default();
+    match Client::connect(config, || player.clone()) {
+      Err(ClientError::Disconnected { cause: Some(cause), .. }) => {
+        assert!(matches!(*cause, ClientError::HeartbeatTimeout))
       }
+      other => panic!("Expected a heartbeat timeout, got {:?}", other.map(|outcomes| outcomes.len())),
+    }
+    assert!(player.round_trips.borrow().is_empty());
+    broadcaster.shutdown().unwrap();
+  }
 
-      InboundMessage::GameLink { url, .. } => {
-        info!("Watch game at: {}", url);
+  #[test]
+  fn invalid_player_name_is_not_retried() {
+    let (port, broadcaster) = start_server(|out: ws::Sender| {
+      move |message: ws::Message| {
+        if message.into_text()?.contains("RegisterPlayer") {
+          out.send(
+            r#"{"type":"se.cygni.snake.api.exception.InvalidPlayerName","receivingPlayerId":"",
+                "PlayerNameInvalidReason":"Taken"}"#,
+          )?;
+        }
+        Ok(())
       }
+    });
 
-      InboundMessage::MapUpdate { map, game_id, game_tick, receiving_player_id, .. } => {
-        debug!("Game map updated, tick: {}", game_tick);
+    let player = ReconnectingPlayer::default();
+    match Client::connect(get_config(port, 3), || player.clone()) {
+      Err(ClientError::InvalidPlayerName(PlayerNameInvalidReason::Taken)) => {}
+      other => panic!("Expected an invalid name, got {:?}", other.map(|outcomes| outcomes.len())),
+    }
+    assert!(player.reconnects.borrow().is_empty());
+    broadcaster.shutdown().unwrap();
+  }
+
+  #[test]
+  fn game_mode_from_the_server_beats_the_venue() {
+    let (port, broadcaster) = start_server(|out: ws::Sender| {
+      move |message: ws::Message| {
+        if message.into_text()?.contains("RegisterPlayer") {
+          // A tournament, although the client was configured for training
+          out.send(REGISTERED)?;
+          let map = json!({"width": 3, "height": 3, "worldTick": 0, "foodPositions": [], "obstaclePositions": [],
+                           "snakeInfos": []});
+          for game_id in &["first", "second"] {
+            let ended = json!({"type": "se.cygni.snake.api.event.GameEndedEvent", "receivingPlayerId": "me",
+                               "playerWinnerId": "me", "gameId": game_id, "gameTick": 8, "map": map});
+            out.send(ended.to_string())?;
+          }
+          out.send(
+            r#"{"type":"se.cygni.snake.api.event.TournamentEndedEvent","receivingPlayerId":"me",
+                "playerWinnerId":"me","gameId":"second","gameResult":[]}"#,
+          )?;
+        }
+        Ok(())
+      }
+    });
 
-        let direction = self.player.get_next_move(&map, &receiving_player_id);
+    let outcomes = Client::connect(get_config(port, 0), ReconnectingPlayer::default).unwrap();
 
-        self.send_message(OutboundMessage::RegisterMove {
-          direction,
-          game_tick,
-          receiving_player_id: &receiving_player_id,
-          game_id: &game_id,
-        })?;
+    assert_eq!(vec!["first", "second"], outcomes.iter().map(|outcome| outcome.game_id.as_str()).collect::<Vec<_>>());
+    broadcaster.shutdown().unwrap();
+  }
+
+  // Thinks for half a second on its first move
+  #[derive(Default)]
+  struct OverrunningPlayer {
+    moves: u32,
+    round_trips: Rc<RefCell<Vec<Duration>>>,
+  }
+
+  impl Player for OverrunningPlayer {
+    fn get_next_move(&mut self, _: &Map, _: &str) -> Direction {
+      self.moves += 1;
+      if self.moves == 1 {
+        thread::sleep(Duration::from_millis(500));
       }
+      Direction::Down
+    }
+
+    fn on_heartbeat(&mut self, round_trip: Duration) {
+      self.round_trips.borrow_mut().push(round_trip);
+    }
+  }
+
+  #[test]
+  fn overrunning_player_does_not_stall_the_session() {
+    let (events, received) = mpsc::channel();
+    let (port, broadcaster) = start_server(move |out: ws::Sender| {
+      let events = events.clone();
+      move |message: ws::Message| {
+        let message: serde_json::Value = serde_json::from_str(&message.into_text()?).unwrap();
+        let message_type = message["type"].as_str().unwrap_or_default();
+        if message_type.ends_with("RegisterPlayer") {
+          out.send(REGISTERED)?;
+          let out = out.clone();
+          // Ticks every 100 ms, whether the player has answered or not
+          thread::spawn(move || {
+            let map = json!({"width": 3, "height": 3, "worldTick": 0, "foodPositions": [], "obstaclePositions": [],
+                             "snakeInfos": [{"id": "me", "name": "me", "points": 0, "positions": [4],
+                                             "tailProtectedForGameTicks": 0}]});
+            for game_tick in 0..8 {
+              let update = json!({"type": "se.cygni.snake.api.event.MapUpdateEvent", "receivingPlayerId": "me",
+                                  "gameId": "game", "gameTick": game_tick, "map": map});
+              out.send(update.to_string()).unwrap();
+              thread::sleep(Duration::from_millis(100));
+            }
+            let ended = json!({"type": "se.cygni.snake.api.event.GameEndedEvent", "receivingPlayerId": "me",
+                               "playerWinnerId": "me", "gameId": "game", "gameTick": 8, "map": map});
+            out.send(ended.to_string()).unwrap();
+            out.close(ws::CloseCode::Normal).unwrap();
+          });
+        } else if message_type.ends_with("HeartBeatRequest") {
+          events.send("heartbeat".to_string()).unwrap();
+          out.send(r#"{"type":"se.cygni.snake.api.response.HeartBeatResponse","receivingPlayerId":"me"}"#)?;
+        } else if message_type.ends_with("RegisterMove") {
+          events.send(format!("move {}", message["gameTick"])).unwrap();
+        }
+        Ok(())
+      }
+    });
+    // Leaves 50 ms of each tick to think
+    let config = Config { move_margin_ms: 200, heartbeat_interval_ms: 20, ..get_config(port, 0) };
+
+    let player = OverrunningPlayer::default();
+    let round_trips = player.round_trips.clone();
+    let outcomes = Client::connect(config, || player).unwrap();
+
+    let events: Vec<String> = received.try_iter().collect();
+    let moves: Vec<_> = events.iter().filter(|event| event.starts_with("move")).cloned().collect();
+    assert_eq!((0..8).map(|game_tick| format!("move {}", game_tick)).collect::<Vec<_>>(), moves);
+    let thinking = events.iter().skip_while(|event| *event != "move 0").take_while(|event| *event != "move 4");
+    assert!(thinking.filter(|event| *event == "heartbeat").count() >= 2, "{:?}", events);
+    assert!(outcomes[0].missed_deadlines >= 4);
+    // The answers that came in while the player was thinking are passed on once it is done
+    assert!(!round_trips.borrow().is_empty());
+    broadcaster.shutdown().unwrap();
+  }
+
+  #[cfg(feature = "tls")]
+  mod tls {
+    use super::*;
+    use openssl::{
+      asn1::Asn1Time,
+      hash::MessageDigest,
+      pkey::{PKey, Private},
+      rsa::Rsa,
+      ssl::{HandshakeError, SslAcceptor, SslMethod, SslStream},
+      x509::{extension::SubjectAlternativeName, X509NameBuilder, X509},
+    };
+    use std::{env, fs};
+
+    fn self_signed_certificate(common_name: &str) -> (X509, PKey<Private>) {
+      let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+      let mut name = X509NameBuilder::new().unwrap();
+      name.append_entry_by_text("CN", common_name).unwrap();
+      let name = name.build();
+
+      let mut builder = X509::builder().unwrap();
+      builder.set_version(2).unwrap();
+      builder.set_subject_name(&name).unwrap();
+      builder.set_issuer_name(&name).unwrap();
+      builder.set_pubkey(&key).unwrap();
+      builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+      builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+      let names = SubjectAlternativeName::new().ip("127.0.0.1").build(&builder.x509v3_context(None, None)).unwrap();
+      builder.append_extension(names).unwrap();
+      builder.sign(&key, MessageDigest::sha256()).unwrap();
+      (builder.build(), key)
+    }
+
+    // Stands in for a TLS terminating proxy, ending the session once the player registers
+    struct TlsServer {
+      out: ws::Sender,
+      acceptor: Arc<SslAcceptor>,
+    }
 
-      InboundMessage::SnakeDead { death_reason, .. } => {
-        debug!("The snake died, the reason was: {:?}", death_reason);
+    impl ws::Handler for TlsServer {
+      fn on_message(&mut self, message: ws::Message) -> ws::Result<()> {
+        if message.into_text()?.contains("RegisterPlayer") {
+          self.out.close(ws::CloseCode::Normal)?;
+        }
+        Ok(())
       }
 
-      InboundMessage::GameEnded { player_winner_id, .. } => {
-        info!("Game ended, the winner is: {}", player_winner_id);
-        if self.config.venue == "training" {
-          self.ws.close(ws::CloseCode::Normal)?;
+      // Finishes the handshake here, as ws drops the connection if it has to wait for the socket halfway through
+      fn upgrade_ssl_server(&mut self, stream: ws::util::TcpStream) -> ws::Result<SslStream<ws::util::TcpStream>> {
+        let mut handshake = self.acceptor.accept(stream);
+        while let Err(HandshakeError::WouldBlock(mid)) = handshake {
+          thread::sleep(Duration::from_millis(1));
+          handshake = mid.handshake();
         }
+        Ok(handshake?)
       }
+    }
+
+    // The port of the server and a file with its certificate
+    fn start_tls_server(name: &str) -> (u16, String, ws::Sender) {
+      let (certificate, key) = self_signed_certificate("127.0.0.1");
+      let file = env::temp_dir().join(name);
+      fs::write(&file, certificate.to_pem().unwrap()).unwrap();
 
-      InboundMessage::TournamentEnded { player_winner_id, .. } => {
-        info!("Tournament ended, the winner is: {}", player_winner_id);
-        self.ws.close(ws::CloseCode::Normal)?;
+      let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+      acceptor.set_certificate(&certificate).unwrap();
+      acceptor.set_private_key(&key).unwrap();
+      let acceptor = Arc::new(acceptor.build());
+
+      let settings = ws::Settings { encrypt_server: true, ..Default::default() };
+      let (port, broadcaster) = start_server_with(settings, move |out| TlsServer { out, acceptor: acceptor.clone() });
+      (port, file.to_str().unwrap().to_string(), broadcaster)
+    }
+
+    #[test]
+    fn client_connects_to_wss_url() {
+      let (port, certificate, broadcaster) = start_tls_server("snakebot-tls-trusted.pem");
+      let config = Config {
+        url: Some(format!("wss://127.0.0.1:{}/training?client=rust", port)),
+        tls_ca_file: Some(certificate),
+        ..get_config(80, 0)
+      };
+
+      assert!(Client::connect(config, ReconnectingPlayer::default).is_ok());
+      broadcaster.shutdown().unwrap();
+    }
+
+    #[test]
+    fn every_certificate_of_a_bundle_is_trusted() {
+      let (port, certificate, broadcaster) = start_tls_server("snakebot-tls-bundled.pem");
+      let bundle = env::temp_dir().join("snakebot-tls-bundle.pem");
+      let (other, _) = self_signed_certificate("Another CA");
+      let mut pem = other.to_pem().unwrap();
+      pem.extend(fs::read(&certificate).unwrap());
+      fs::write(&bundle, pem).unwrap();
+      let config = Config {
+        url: Some(format!("wss://127.0.0.1:{}/training", port)),
+        tls_ca_file: Some(bundle.to_str().unwrap().to_string()),
+        ..get_config(80, 0)
+      };
+
+      assert!(Client::connect(config, ReconnectingPlayer::default).is_ok());
+      broadcaster.shutdown().unwrap();
+    }
+
+    #[test]
+    fn untrusted_certificate_is_rejected() {
+      let (port, _, broadcaster) = start_tls_server("snakebot-tls-untrusted.pem");
+      let config = Config { url: Some(format!("wss://127.0.0.1:{}/training", port)), ..get_config(80, 0) };
+
+      match Client::connect(config, ReconnectingPlayer::default) {
+        Err(ClientError::Disconnected { cause: Some(_), .. }) => {}
+        other => panic!("Expected the certificate to be rejected, got {:?}", other.map(|outcomes| outcomes.len())),
       }
+      broadcaster.shutdown().unwrap();
+    }
 
-      _ => {}
+    #[test]
+    fn unusable_ca_file_is_not_retried() {
+      let not_a_certificate = env::temp_dir().join("snakebot-tls-not-a-certificate.pem");
+      fs::write(&not_a_certificate, "not a certificate").unwrap();
+      let missing = env::temp_dir().join("snakebot-tls-missing.pem");
+      let _ = fs::remove_file(&missing);
+
+      for file in &[not_a_certificate, missing] {
+        let player = ReconnectingPlayer::default();
+        let config = Config {
+          url: Some("wss://127.0.0.1:1/training".to_string()),
+          tls_ca_file: Some(file.to_str().unwrap().to_string()),
+          reconnect_delay_ms: 60_000,
+          ..get_config(80, 3)
+        };
+        match Client::connect(config, || player.clone()) {
+          Err(ClientError::InvalidTlsCaFile { .. }) => {}
+          other => panic!("Expected {:?} to be rejected, got {:?}", file, other.map(|outcomes| outcomes.len())),
+        }
+        assert!(player.reconnects.borrow().is_empty());
+      }
     }
-    Ok(())
   }
 }
@@ -0,0 +1,516 @@
+use crate::{
+    client::{notify, Player},
+    simulation::{apply_moves, SnakeDeath},
+    types::{DeathReason, Direction, GameSettings, InboundMessage, Map, PlayerRank, Position, SnakeInfo},
+    utils::{Coordinate, DIRECTIONS},
+};
+use log::debug;
+use rand::{rngs::StdRng, seq::SliceRandom, FromEntropy, Rng, SeedableRng};
+use std::collections::HashMap;
+
+pub const DEFAULT_MAP_WIDTH: i32 = 46;
+pub const DEFAULT_MAP_HEIGHT: i32 = 34;
+const DEFAULT_MAX_GAME_TICKS: u32 = 1000;
+const NOOF_OBSTACLES: usize = 5;
+const MAX_OBSTACLE_SIZE: i32 = 3;
+
+#[derive(Clone, Debug)]
+pub struct GameOptions {
+    pub width: i32,
+    pub height: i32,
+    pub max_game_ticks: u32,
+    pub seed: Option<u64>,
+}
+
+impl Default for GameOptions {
+    fn default() -> GameOptions {
+        GameOptions {
+            width: DEFAULT_MAP_WIDTH,
+            height: DEFAULT_MAP_HEIGHT,
+            max_game_ticks: DEFAULT_MAX_GAME_TICKS,
+            seed: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Death {
+    pub player_id: String,
+    pub game_tick: u32,
+    pub coordinate: Coordinate,
+    pub reason: DeathReason,
+}
+
+// The state of a single game, advanced one tick at a time with the moves of all snakes
+pub struct Game {
+    game_id: String,
+    settings: GameSettings,
+    max_game_ticks: u32,
+    map: Map,
+    game_tick: u32,
+    directions: Vec<Direction>,
+    rng: StdRng,
+}
+
+impl Game {
+    pub fn new(
+        game_id: String,
+        settings: GameSettings,
+        options: &GameOptions,
+        players: &[(String, String)],
+        seed: u64,
+    ) -> Game {
+        let mut game = Game {
+            game_id,
+            settings,
+            max_game_ticks: options.max_game_ticks,
+            map: Map {
+                width: options.width,
+                height: options.height,
+                world_tick: 0,
+                snake_infos: Vec::new(),
+                food_positions: Vec::new(),
+                obstacle_positions: Vec::new(),
+            },
+            game_tick: 0,
+            directions: vec![Direction::Down; players.len()],
+            rng: StdRng::seed_from_u64(seed),
+        };
+
+        if game.settings.obstacles_enabled {
+            game.place_obstacles();
+        }
+
+        for (id, name) in players {
+            let position = game.random_start_position().expect("The map is too small for all snakes");
+            game.map.snake_infos.push(SnakeInfo {
+                id: id.clone(),
+                name: name.clone(),
+                points: 0,
+                positions: vec![position; game.settings.start_snake_length.max(1) as usize],
+                tail_protected_for_game_ticks: 0,
+            });
+        }
+
+        game
+    }
+
+    pub fn game_id(&self) -> &str {
+        &self.game_id
+    }
+
+    pub fn game_tick(&self) -> u32 {
+        self.game_tick
+    }
+
+    pub fn settings(&self) -> &GameSettings {
+        &self.settings
+    }
+
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+    pub fn is_alive(&self, player_id: &str) -> bool {
+        self.map.get_snake_by_id(player_id).is_some_and(|snake| !snake.positions.is_empty())
+    }
+
+    pub fn noof_alive(&self) -> usize {
+        self.map.snake_infos.iter().filter(|snake| !snake.positions.is_empty()).count()
+    }
+
+    pub fn is_over(&self) -> bool {
+        let alive = self.noof_alive();
+        alive == 0 || (self.map.snake_infos.len() > 1 && alive <= 1) || self.game_tick >= self.max_game_ticks
+    }
+
+    // Snakes without a registered move keep going in their previous direction
+    pub fn tick(&mut self, moves: &HashMap<String, Direction>) -> Vec<Death> {
+        self.game_tick += 1;
+
+        for (index, snake) in self.map.snake_infos.iter().enumerate() {
+            if let Some(&direction) = moves.get(&snake.id) {
+                self.directions[index] = direction;
+            }
+        }
+        let moves: HashMap<String, Direction> = self
+            .map
+            .snake_infos
+            .iter()
+            .zip(&self.directions)
+            .map(|(snake, &direction)| (snake.id.clone(), direction))
+            .collect();
+        let deaths = apply_moves(&mut self.map, &self.settings, &moves);
+
+        if self.settings.food_enabled {
+            self.update_food();
+        }
+
+        let game_tick = self.game_tick;
+        deaths
+            .into_iter()
+            .map(|SnakeDeath { player_id, coordinate, reason }| Death { player_id, game_tick, coordinate, reason })
+            .collect()
+    }
+
+    // Ranks by points, surviving snakes before dead ones on equal points
+    pub fn player_ranks(&self) -> Vec<PlayerRank> {
+        let mut snakes: Vec<&SnakeInfo> = self.map.snake_infos.iter().collect();
+        snakes.sort_by_key(|snake| (-snake.points, snake.positions.is_empty()));
+        snakes
+            .into_iter()
+            .enumerate()
+            .map(|(index, snake)| PlayerRank {
+                player_name: snake.name.clone(),
+                player_id: snake.id.clone(),
+                rank: index as i32 + 1,
+                points: snake.points,
+                alive: !snake.positions.is_empty(),
+            })
+            .collect()
+    }
+
+    pub fn winner(&self) -> Option<&SnakeInfo> {
+        let winner_id = self.player_ranks().into_iter().next()?.player_id;
+        self.map.get_snake_by_id(&winner_id)
+    }
+
+    pub fn game_starting_message(&self, receiving_player_id: &str) -> InboundMessage {
+        InboundMessage::GameStarting {
+            receiving_player_id: receiving_player_id.to_string(),
+            game_id: self.game_id.clone(),
+            noof_players: self.map.snake_infos.len() as u32,
+            width: self.map.width as u32,
+            height: self.map.height as u32,
+            game_settings: self.settings.clone(),
+        }
+    }
+
+    pub fn map_update_message(&self, receiving_player_id: &str) -> InboundMessage {
+        InboundMessage::MapUpdate {
+            receiving_player_id: receiving_player_id.to_string(),
+            game_id: self.game_id.clone(),
+            game_tick: self.game_tick,
+            map: self.map.clone(),
+        }
+    }
+
+    pub fn snake_dead_message(&self, receiving_player_id: &str, death: &Death) -> InboundMessage {
+        let Coordinate(x, y) = death.coordinate;
+        InboundMessage::SnakeDead {
+            receiving_player_id: receiving_player_id.to_string(),
+            game_id: self.game_id.clone(),
+            game_tick: death.game_tick,
+            player_id: death.player_id.clone(),
+            x: x as u32,
+            y: y as u32,
+            death_reason: death.reason,
+        }
+    }
+
+    pub fn game_ended_message(&self, receiving_player_id: &str) -> InboundMessage {
+        let (player_winner_id, player_winner_name) =
+            self.winner().map(|snake| (snake.id.clone(), snake.name.clone())).unwrap_or_default();
+        InboundMessage::GameEnded {
+            receiving_player_id: receiving_player_id.to_string(),
+            player_winner_id,
+            player_winner_name,
+            game_id: self.game_id.clone(),
+            game_tick: self.game_tick,
+            map: self.map.clone(),
+        }
+    }
+
+    pub fn game_result_message(&self, receiving_player_id: &str) -> InboundMessage {
+        InboundMessage::GameResult {
+            receiving_player_id: receiving_player_id.to_string(),
+            game_id: self.game_id.clone(),
+            player_ranks: self.player_ranks(),
+        }
+    }
+
+    fn is_free(&self, position: Position) -> bool {
+        !self.map.obstacle_positions.contains(&position)
+            && !self.map.food_positions.contains(&position)
+            && !self.map.snake_infos.iter().any(|snake| snake.positions.contains(&position))
+    }
+
+    fn free_positions(&self) -> Vec<Position> {
+        (0..self.map.width * self.map.height).filter(|&position| self.is_free(position)).collect()
+    }
+
+    // Prefers tiles where the snake cannot die on its first move
+    fn random_start_position(&mut self) -> Option<Position> {
+        let free = self.free_positions();
+        let width = self.map.width;
+        let roomy: Vec<Position> = free
+            .iter()
+            .cloned()
+            .filter(|&position| {
+                let coordinate = Coordinate::from_position(position, width);
+                DIRECTIONS.iter().all(|direction| {
+                    let neighbour = coordinate + direction.to_movement_delta();
+                    self.map.inside_map(neighbour) && self.is_free(neighbour.to_position(width))
+                })
+            })
+            .collect();
+        let candidates = if roomy.is_empty() { free } else { roomy };
+        candidates.choose(&mut self.rng).cloned()
+    }
+
+    fn place_obstacles(&mut self) {
+        for _ in 0..NOOF_OBSTACLES {
+            let width = self.rng.gen_range(1, MAX_OBSTACLE_SIZE + 1).min(self.map.width);
+            let height = self.rng.gen_range(1, MAX_OBSTACLE_SIZE + 1).min(self.map.height);
+            let x0 = self.rng.gen_range(0, self.map.width - width + 1);
+            let y0 = self.rng.gen_range(0, self.map.height - height + 1);
+            for y in y0..y0 + height {
+                for x in x0..x0 + width {
+                    let position = Coordinate(x, y).to_position(self.map.width);
+                    if !self.map.obstacle_positions.contains(&position) {
+                        self.map.obstacle_positions.push(position);
+                    }
+                }
+            }
+        }
+    }
+
+    fn update_food(&mut self) {
+        if self.rng.gen_range(0, 100) < self.settings.add_food_likelihood {
+            if let Some(&position) = self.free_positions().choose(&mut self.rng) {
+                self.map.food_positions.push(position);
+            }
+        }
+        if !self.map.food_positions.is_empty() && self.rng.gen_range(0, 100) < self.settings.remove_food_likelihood {
+            let index = self.rng.gen_range(0, self.map.food_positions.len());
+            self.map.food_positions.remove(index);
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GameSummary {
+    pub game_id: String,
+    pub game_tick: u32,
+    pub player_winner_id: String,
+    pub player_ranks: Vec<PlayerRank>,
+    pub deaths: Vec<Death>,
+    pub map: Map,
+}
+
+struct Entrant {
+    id: String,
+    name: String,
+    player: Box<dyn Player>,
+}
+
+// Runs whole games locally between players, delivering the same messages as the server
+pub struct Simulator {
+    settings: GameSettings,
+    options: GameOptions,
+    entrants: Vec<Entrant>,
+    rng: StdRng,
+    noof_games: u32,
+}
+
+impl Simulator {
+    pub fn new(settings: GameSettings, options: GameOptions) -> Simulator {
+        let rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Simulator { settings, options, entrants: Vec::new(), rng, noof_games: 0 }
+    }
+
+    pub fn add_player<P: Player + 'static>(&mut self, name: &str, player: P) -> String {
+        let id = format!("player-{}", self.entrants.len() + 1);
+        self.entrants.push(Entrant { id: id.clone(), name: name.to_string(), player: Box::new(player) });
+        id
+    }
+
+    pub fn run_game(&mut self) -> GameSummary {
+        self.noof_games += 1;
+        let players: Vec<(String, String)> = self.entrants.iter().map(|e| (e.id.clone(), e.name.clone())).collect();
+        let mut game = Game::new(
+            format!("game-{}", self.noof_games),
+            self.settings.clone(),
+            &self.options,
+            &players,
+            self.rng.gen(),
+        );
+        let mut deaths = Vec::new();
+
+        for entrant in self.entrants.iter_mut() {
+            notify(entrant.player.as_mut(), &game.game_starting_message(&entrant.id));
+        }
+
+        while !game.is_over() {
+            let mut moves = HashMap::new();
+            for entrant in self.entrants.iter_mut().filter(|e| game.is_alive(&e.id)) {
+                notify(entrant.player.as_mut(), &game.map_update_message(&entrant.id));
+                moves.insert(entrant.id.clone(), entrant.player.get_next_move(game.map(), &entrant.id));
+            }
+
+            for death in game.tick(&moves) {
+                debug!("Snake {} died: {:?}", death.player_id, death.reason);
+                for entrant in self.entrants.iter_mut() {
+                    notify(entrant.player.as_mut(), &game.snake_dead_message(&entrant.id, &death));
+                }
+                deaths.push(death);
+            }
+        }
+
+        for entrant in self.entrants.iter_mut() {
+            notify(entrant.player.as_mut(), &game.game_ended_message(&entrant.id));
+            notify(entrant.player.as_mut(), &game.game_result_message(&entrant.id));
+        }
+
+        GameSummary {
+            game_id: game.game_id().to_string(),
+            game_tick: game.game_tick(),
+            player_winner_id: game.winner().map(|snake| snake.id.clone()).unwrap_or_default(),
+            player_ranks: game.player_ranks(),
+            deaths,
+            map: game.map().clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bots::SafeBot;
+
+    const MAP_WIDTH: i32 = 5;
+
+    fn get_settings() -> GameSettings {
+        GameSettings {
+            obstacles_enabled: false,
+            food_enabled: false,
+            spontaneous_growth_every_n_world_tick: 0,
+            ..Default::default()
+        }
+    }
+
+    fn get_options() -> GameOptions {
+        GameOptions { width: MAP_WIDTH, height: MAP_WIDTH, max_game_ticks: 100, seed: Some(1) }
+    }
+
+    fn get_game(snakes: Vec<Vec<Coordinate>>) -> Game {
+        let players: Vec<(String, String)> =
+            (0..snakes.len()).map(|i| (i.to_string(), format!("snake-{}", i))).collect();
+        let mut game = Game::new("test".to_string(), get_settings(), &get_options(), &players, 0);
+        for (snake, coordinates) in game.map.snake_infos.iter_mut().zip(snakes) {
+            snake.positions = coordinates.iter().map(|c| c.to_position(MAP_WIDTH)).collect();
+        }
+        game
+    }
+
+    fn moves(directions: &[Direction]) -> HashMap<String, Direction> {
+        directions.iter().enumerate().map(|(i, d)| (i.to_string(), *d)).collect()
+    }
+
+    #[test]
+    fn snake_moves_and_keeps_its_length() {
+        let mut game = get_game(vec![vec![Coordinate(2, 2), Coordinate(2, 3)]]);
+        let deaths = game.tick(&moves(&[Direction::Up]));
+
+        assert!(deaths.is_empty());
+        assert_eq!(
+            vec![Coordinate(2, 1).to_position(MAP_WIDTH), Coordinate(2, 2).to_position(MAP_WIDTH)],
+            game.map().snake_infos[0].positions
+        );
+    }
+
+    #[test]
+    fn snake_grows_when_eating_food() {
+        let mut game = get_game(vec![vec![Coordinate(2, 2)]]);
+        game.map.food_positions.push(Coordinate(3, 2).to_position(MAP_WIDTH));
+        game.tick(&moves(&[Direction::Right]));
+
+        let snake = &game.map().snake_infos[0];
+        assert_eq!(2, snake.positions.len());
+        assert_eq!(get_settings().points_per_food as i32 + get_settings().points_per_length as i32, snake.points);
+        assert!(game.map().food_positions.is_empty());
+    }
+
+    #[test]
+    fn snake_dies_when_moving_into_wall() {
+        let mut game = get_game(vec![vec![Coordinate(0, 0)], vec![Coordinate(4, 4)]]);
+        let deaths = game.tick(&moves(&[Direction::Left, Direction::Up]));
+
+        assert_eq!(1, deaths.len());
+        assert_eq!("0", deaths[0].player_id);
+        assert_eq!(DeathReason::CollisionWithWall, deaths[0].reason);
+        assert!(!game.is_alive("0"));
+        assert!(game.is_over());
+    }
+
+    #[test]
+    fn head_to_head_kills_both_snakes() {
+        let mut game = get_game(vec![vec![Coordinate(1, 2)], vec![Coordinate(3, 2)]]);
+        let deaths = game.tick(&moves(&[Direction::Right, Direction::Left]));
+
+        assert_eq!(2, deaths.len());
+        assert!(deaths.iter().all(|death| death.reason == DeathReason::CollisionWithSnake));
+    }
+
+    #[test]
+    fn snake_dies_when_moving_into_other_body_and_gives_points() {
+        let mut game = get_game(vec![
+            vec![Coordinate(1, 1)],
+            vec![Coordinate(2, 0), Coordinate(2, 1), Coordinate(2, 2), Coordinate(2, 3)],
+        ]);
+        let deaths = game.tick(&moves(&[Direction::Right, Direction::Right]));
+
+        assert_eq!(1, deaths.len());
+        assert_eq!(DeathReason::CollisionWithSnake, deaths[0].reason);
+        assert_eq!(get_settings().points_per_caused_death as i32, game.map().snake_infos[1].points);
+    }
+
+    #[test]
+    fn head_to_tail_nibbles_and_protects_tail() {
+        let mut game = get_game(vec![
+            vec![Coordinate(1, 3), Coordinate(0, 3)],
+            vec![Coordinate(2, 0), Coordinate(2, 1), Coordinate(2, 2), Coordinate(2, 3), Coordinate(2, 4)],
+        ]);
+        let deaths = game.tick(&moves(&[Direction::Right, Direction::Right]));
+
+        assert!(deaths.is_empty());
+        assert_eq!(4, game.map().snake_infos[1].positions.len());
+        assert_eq!(get_settings().points_per_nibble as i32, game.map().snake_infos[0].points);
+        assert_eq!(
+            get_settings().noof_rounds_tail_protected_after_nibble,
+            game.map().snake_infos[0].tail_protected_for_game_ticks
+        );
+    }
+
+    #[test]
+    fn simulator_runs_full_game_with_results() {
+        let mut simulator =
+            Simulator::new(GameSettings::default(), GameOptions { seed: Some(7), ..Default::default() });
+        simulator.add_player("one", SafeBot::new());
+        simulator.add_player("two", SafeBot::new());
+        let summary = simulator.run_game();
+
+        assert_eq!(2, summary.player_ranks.len());
+        assert_eq!(summary.player_winner_id, summary.player_ranks[0].player_id);
+        assert!(summary.game_tick > 0);
+    }
+
+    #[test]
+    fn simulator_is_deterministic_for_a_seed() {
+        let run = || {
+            let mut simulator =
+                Simulator::new(GameSettings::default(), GameOptions { seed: Some(3), ..Default::default() });
+            simulator.add_player("one", SafeBot::new());
+            simulator.add_player("two", SafeBot::new());
+            simulator.run_game()
+        };
+        let (first, second) = (run(), run());
+
+        assert_eq!(first.game_tick, second.game_tick);
+        assert_eq!(first.deaths, second.deaths);
+        assert_eq!(first.map.food_positions, second.map.food_positions);
+    }
+}
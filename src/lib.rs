@@ -0,0 +1,37 @@
+//! A client library for the Cygni snakebot server.
+//!
+//! Implement [`Player`] for your snake and hand it to [`Client::connect`], or to [`AsyncClient::connect`] on a tokio
+//! runtime, or run games locally with [`engine::Simulator`] and [`server::Server`].
+#![deny(clippy::all)]
+// ws::Error and tungstenite::Error are large, but they are what the ws handlers and ClientError carry
+#![allow(clippy::result_large_err)]
+pub mod async_client;
+pub mod bitboard;
+pub mod board;
+pub mod bots;
+pub mod client;
+pub mod engine;
+pub mod mcts;
+pub mod minimax;
+pub mod pathfinding;
+pub mod render;
+pub mod replay;
+pub mod server;
+pub mod simulation;
+pub mod types;
+pub mod utils;
+pub mod viewer;
+
+pub use crate::{
+    async_client::AsyncClient,
+    board::{Board, Cell},
+    client::{notify, Client, ClientError, Config, GameOutcome, Player},
+    pathfinding::{Path, ReachableArea, Territory, TieBreak},
+    render::Renderer,
+    simulation::{next_map, SnakeDeath},
+    types::{
+        DeathReason, Direction, GameMode, GameResult, GameSettings, InboundMessage, Map, OutboundMessage, PlayerRank,
+        Position, SnakeInfo,
+    },
+    utils::{Coordinate, Tile},
+};
@@ -1,72 +1,415 @@
 #![deny(clippy::all)]
-use crate::{
-    client::{Client, Config},
-    snake::Snake,
-};
-use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg};
+use crate::snake::Snake;
+use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg, ArgMatches, SubCommand};
+use config::{ConfigError, Environment, File, FileFormat};
 use env_logger::Builder;
 use log::{info, LevelFilter};
-use std::path::Path;
-mod client;
+use snakebot_rust::{
+    bots::SafeBot,
+    client::{
+        DEFAULT_GAMES, DEFAULT_HEARTBEAT_INTERVAL_MS, DEFAULT_HEARTBEAT_TIMEOUT_MS, DEFAULT_HOST,
+        DEFAULT_MAX_RECONNECT_DELAY_MS, DEFAULT_MOVE_MARGIN_MS, DEFAULT_PORT, DEFAULT_RECONNECT_ATTEMPTS,
+        DEFAULT_RECONNECT_DELAY_MS, DEFAULT_SNAKE_NAME, DEFAULT_VENUE,
+    },
+    mcts::MctsBot,
+    minimax::MinimaxBot,
+    replay::{read_replay, replay_decisions, ReplayEntry},
+    viewer::{browse, LiveViewer, Timeline},
+    Client, ClientError, Config, Player,
+};
+use std::process;
+use url::Url;
 mod snake;
-mod types;
-mod utils;
 
 const CONFIG_FILE: &str = "snake.conf";
-const DEFAULT_HOST: &str = "snake.cygni.se";
-const DEFAULT_PORT: &str = "80";
-const DEFAULT_SNAKE_NAME: &str = "default-rust-snake-name";
-const DEFAULT_VENUE: &str = "training";
-
-fn read_config() -> Config {
-    info!("Reading config from file at {:?}", Path::new(CONFIG_FILE).canonicalize());
-    let matches = app_from_crate!()
+const ENVIRONMENT_PREFIX: &str = "SNAKE";
+const VENUES: [&str; 2] = ["tournament", "training"];
+const PLAYERS: [&str; 4] = ["snake", "safe", "minimax", "mcts"];
+const REPLAY_MAX_DEPTH: u32 = 4;
+const REPLAY_ITERATIONS: u32 = 2_000;
+const REPLAY_SEED: u64 = 0;
+
+// Command line arguments and the config keys they override
+const OVERRIDES: [(&str, &str); 8] = [
+    ("host", "host"),
+    ("port", "port"),
+    ("venue", "venue"),
+    ("snake-name", "snake_name"),
+    ("reconnect-attempts", "reconnect_attempts"),
+    ("games", "games"),
+    ("record", "replay_directory"),
+    ("url", "url"),
+];
+
+fn parse_args<'a>() -> ArgMatches<'a> {
+    app_from_crate!()
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .help("The config file to read [default: snake.conf]")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("host")
                 .short("h")
                 .long("host")
-                .help("The host to connect to")
-                .takes_value(true)
-                .default_value(DEFAULT_HOST),
+                .help("The host to connect to [default: snake.cygni.se]")
+                .takes_value(true),
         )
         .arg(
             Arg::with_name("port")
                 .short("p")
                 .long("port")
-                .help("The port to connect to")
-                .takes_value(true)
-                .default_value(DEFAULT_PORT),
+                .help("The port to connect to [default: 80]")
+                .takes_value(true),
         )
         .arg(
             Arg::with_name("venue")
                 .short("v")
                 .long("venue")
-                .help("The venue (tournament or training)")
+                .help("The venue (tournament or training) [default: training]")
                 .takes_value(true)
-                .default_value(DEFAULT_VENUE)
-                .possible_values(&["tournament", "training"]),
+                .possible_values(&VENUES),
         )
         .arg(
             Arg::with_name("snake-name")
                 .short("n")
                 .long("snake-name")
-                .help("The name of the snake")
-                .takes_value(true)
-                .default_value(DEFAULT_SNAKE_NAME),
+                .help("The name of the snake [default: default-rust-snake-name]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("reconnect-attempts")
+                .short("r")
+                .long("reconnect-attempts")
+                .help("How many times to reconnect after losing the connection [default: 5]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("games")
+                .short("g")
+                .long("games")
+                .help("The number of training games to play back-to-back, 0 plays forever [default: 1]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("forever")
+                .short("f")
+                .long("forever")
+                .help("Keep playing training games until stopped")
+                .conflicts_with("games"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("DIRECTORY")
+                .help("Write a replay of every game to DIRECTORY")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("url")
+                .long("url")
+                .help("Connect to this ws:// or wss:// URL instead of the one made from host, port and venue")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("view").long("view").help("Show the game in the terminal while playing"))
+        .subcommand(
+            SubCommand::with_name("view")
+                .about("Steps through a recorded game in the terminal")
+                .arg(Arg::with_name("FILE").help("The replay file to read").required(true)),
         )
-        .get_matches();
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Feeds a recorded game to a player and reports the ticks where it decides differently")
+                .arg(Arg::with_name("FILE").help("The replay file to read").required(true))
+                .arg(
+                    Arg::with_name("player")
+                        .short("p")
+                        .long("player")
+                        .help("The player to replay the game with")
+                        .takes_value(true)
+                        .possible_values(&PLAYERS)
+                        .default_value("snake"),
+                ),
+        )
+        .get_matches()
+}
+
+fn read_config(matches: &ArgMatches) -> Result<Config, ConfigError> {
+    // A missing default config file is fine, but one given explicitly has to exist
+    let (file, required) = match matches.value_of("config") {
+        Some(file) => (file, true),
+        None => (CONFIG_FILE, false),
+    };
+    let mut overrides: Vec<(&str, &str)> =
+        OVERRIDES.iter().filter_map(|&(arg, key)| matches.value_of(arg).map(|value| (key, value))).collect();
+    if matches.is_present("forever") {
+        overrides.push(("games", "0"));
+    }
+
+    build_config(file, required, &overrides)
+}
+
+// Layers the defaults, the config file, SNAKE_* environment variables and the overrides, in increasing priority
+fn build_config(file: &str, required: bool, overrides: &[(&str, &str)]) -> Result<Config, ConfigError> {
+    let mut settings = config::Config::default();
+    settings.set_default("host", DEFAULT_HOST)?;
+    settings.set_default("port", i64::from(DEFAULT_PORT))?;
+    settings.set_default("venue", DEFAULT_VENUE)?;
+    settings.set_default("snake_name", DEFAULT_SNAKE_NAME)?;
+    settings.set_default("reconnect_attempts", i64::from(DEFAULT_RECONNECT_ATTEMPTS))?;
+    settings.set_default("reconnect_delay_ms", DEFAULT_RECONNECT_DELAY_MS as i64)?;
+    settings.set_default("max_reconnect_delay_ms", DEFAULT_MAX_RECONNECT_DELAY_MS as i64)?;
+    settings.set_default("games", i64::from(DEFAULT_GAMES))?;
+    settings.set_default("move_margin_ms", DEFAULT_MOVE_MARGIN_MS as i64)?;
+    settings.set_default("heartbeat_interval_ms", DEFAULT_HEARTBEAT_INTERVAL_MS as i64)?;
+    settings.set_default("heartbeat_timeout_ms", DEFAULT_HEARTBEAT_TIMEOUT_MS as i64)?;
+
+    info!("Reading config from file at {:?}", file);
+    settings.merge(File::new(file, FileFormat::Ini).required(required))?;
+    settings.merge(Environment::with_prefix(ENVIRONMENT_PREFIX))?;
+    for &(key, value) in overrides {
+        settings.set(key, value)?;
+    }
+
+    let port = get_number(&settings, "port")?;
+    if !(1..=65535).contains(&port) {
+        return Err(ConfigError::Message(format!("port {} is not between 1 and 65535", port)));
+    }
+    let url = settings.get_str("url").ok().filter(|url| !url.is_empty());
+    let mut venue = settings.get_str("venue")?;
+    if let Some(ref url) = url {
+        let parsed =
+            Url::parse(url).map_err(|error| ConfigError::Message(format!("invalid url {:?}: {}", url, error)))?;
+        if parsed.scheme() != "ws" && parsed.scheme() != "wss" {
+            return Err(ConfigError::Message(format!("url {:?} is not a ws:// or wss:// URL", url)));
+        }
+        // Only a fallback, once registered the client goes by the game mode the server reports
+        if let Some(path_venue) = parsed.path_segments().and_then(|mut segments| segments.rfind(|s| !s.is_empty())) {
+            if VENUES.contains(&path_venue) {
+                venue = path_venue.to_string();
+            }
+        }
+    }
+    if !VENUES.contains(&venue.as_str()) {
+        return Err(ConfigError::Message(format!("venue {:?} is not one of {:?}", venue, VENUES)));
+    }
+
+    Ok(Config {
+        host: settings.get_str("host")?,
+        port: port as i32,
+        venue,
+        snake_name: settings.get_str("snake_name")?,
+        reconnect_attempts: get_unsigned(&settings, "reconnect_attempts")? as u32,
+        reconnect_delay_ms: get_unsigned(&settings, "reconnect_delay_ms")?,
+        max_reconnect_delay_ms: get_unsigned(&settings, "max_reconnect_delay_ms")?,
+        games: match get_unsigned(&settings, "games")? {
+            0 => None,
+            games => Some(games as u32),
+        },
+        move_margin_ms: get_unsigned(&settings, "move_margin_ms")?,
+        replay_directory: settings.get_str("replay_directory").ok().filter(|directory| !directory.is_empty()),
+        heartbeat_interval_ms: get_unsigned(&settings, "heartbeat_interval_ms")?.max(1),
+        heartbeat_timeout_ms: get_unsigned(&settings, "heartbeat_timeout_ms")?.max(1),
+        url,
+        tls_ca_file: settings.get_str("tls_ca_file").ok().filter(|file| !file.is_empty()),
+    })
+}
+
+fn get_number(settings: &config::Config, key: &str) -> Result<i64, ConfigError> {
+    settings.get_int(key).map_err(|error| ConfigError::Message(format!("invalid {}: {}", key, error)))
+}
+
+fn get_unsigned(settings: &config::Config, key: &str) -> Result<u64, ConfigError> {
+    let value = get_number(settings, key)?;
+    if value < 0 || value > i64::from(u32::MAX) {
+        return Err(ConfigError::Message(format!("{} {} is out of range", key, value)));
+    }
+    Ok(value as u64)
+}
 
-    Config {
-        host: matches.value_of("host").unwrap_or(DEFAULT_HOST).to_string(),
-        port: matches.value_of("port").unwrap_or(DEFAULT_PORT).parse::<i32>().unwrap(),
-        venue: matches.value_of("venue").unwrap_or(DEFAULT_VENUE).to_string(),
-        snake_name: matches.value_of("snake-name").unwrap_or(DEFAULT_SNAKE_NAME).to_string(),
+// Scripts running the snake can tell a server that is down from a rejected name, invalid configuration exits with 1
+fn exit_code(error: &ClientError) -> i32 {
+    match error {
+        ClientError::InvalidTlsCaFile { .. } => 1,
+        ClientError::Disconnected { cause: Some(cause), .. } => exit_code(cause),
+        ClientError::Disconnected { cause: None, .. } | ClientError::WebSocket(_) => 2,
+        ClientError::Closed { .. } => 3,
+        ClientError::HeartbeatTimeout => 4,
+        ClientError::InvalidPlayerName(_) => 5,
+        ClientError::SerdeJson(_) | ClientError::Io(_) => 6,
+    }
+}
+
+// The searches stop at a fixed budget instead of the time they are given, and the random one is seeded, so that
+// replaying a game twice gives the same decisions
+fn create_player(name: &str) -> Box<dyn Player> {
+    match name {
+        "safe" => Box::new(SafeBot::new()),
+        "minimax" => Box::new(MinimaxBot::new().max_depth(REPLAY_MAX_DEPTH)),
+        "mcts" => Box::new(MctsBot::new().seed(REPLAY_SEED).max_iterations(REPLAY_ITERATIONS)),
+        _ => Box::new(Snake::new()),
+    }
+}
+
+fn read_replay_or_exit(file: &str) -> Vec<ReplayEntry> {
+    read_replay(file).unwrap_or_else(|error| {
+        eprintln!("Could not read replay {}: {}", file, error);
+        process::exit(1);
+    })
+}
+
+fn replay(matches: &ArgMatches) {
+    let entries = read_replay_or_exit(matches.value_of("FILE").unwrap());
+    let mut player = create_player(matches.value_of("player").unwrap());
+
+    let decisions = replay_decisions(&entries, player.as_mut());
+    let differences: Vec<_> = decisions.iter().filter(|decision| decision.differs()).collect();
+    for decision in &differences {
+        match decision.recorded {
+            Some(recorded) => {
+                println!("Tick {}: recorded {:?}, replayed {:?}", decision.game_tick, recorded, decision.replayed)
+            }
+            None => println!("Tick {}: no move recorded, replayed {:?}", decision.game_tick, decision.replayed),
+        }
+    }
+    println!("{} of {} decisions differ", differences.len(), decisions.len());
+}
+
+fn view(matches: &ArgMatches) {
+    let timeline = Timeline::from_replay(&read_replay_or_exit(matches.value_of("FILE").unwrap()));
+    if let Err(error) = browse(&timeline) {
+        eprintln!("Could not show the replay: {}", error);
+        process::exit(1);
     }
 }
 
 fn main() {
-    Builder::from_default_env().filter_module(crate_name!(), LevelFilter::Info).init();
+    let matches = parse_args();
+    // Log lines would scroll the game off the screen
+    let viewing = matches.is_present("view") || matches.subcommand_matches("view").is_some();
+    let level = if viewing { LevelFilter::Warn } else { LevelFilter::Info };
+    Builder::from_default_env().filter_module(crate_name!(), level).init();
+
+    if let Some(matches) = matches.subcommand_matches("replay") {
+        replay(matches);
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("view") {
+        view(matches);
+        return;
+    }
 
-    let config = read_config();
-    Client::connect(config, Snake::new).unwrap();
+    let config = read_config(&matches).unwrap_or_else(|error| {
+        eprintln!("Invalid configuration: {}", error);
+        process::exit(1);
+    });
+    let result = if matches.is_present("view") {
+        Client::connect(config, || LiveViewer::new(Snake::new()))
+    } else {
+        Client::connect(config, Snake::new)
+    };
+    let outcomes = result.unwrap_or_else(|error| {
+        eprintln!("Could not play: {}", error);
+        process::exit(exit_code(&error));
+    });
+    if outcomes.len() > 1 {
+        let won = outcomes.iter().filter(|outcome| outcome.won).count();
+        let points: i32 = outcomes.iter().map(|outcome| outcome.points).sum();
+        let missed_deadlines: u32 = outcomes.iter().map(|outcome| outcome.missed_deadlines).sum();
+        info!(
+            "Won {} of {} games ({:.1}%), {:.1} points per game, {} missed deadlines",
+            won,
+            outcomes.len(),
+            100.0 * won as f64 / outcomes.len() as f64,
+            f64::from(points) / outcomes.len() as f64,
+            missed_deadlines
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use snakebot_rust::client::CloseCode;
+    use std::{env, fs};
+
+    fn write_config_file(name: &str, content: &str) -> String {
+        let path = env::temp_dir().join(name);
+        fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn defaults_are_used_without_file() {
+        let config = build_config("does-not-exist.conf", false, &[]).unwrap();
+
+        let defaults = Config::default();
+        assert_eq!(defaults.host, config.host);
+        assert_eq!(defaults.port, config.port);
+        assert_eq!(defaults.venue, config.venue);
+        assert_eq!(defaults.games, config.games);
+        assert_eq!(None, config.replay_directory);
+        assert_eq!(defaults.heartbeat_timeout_ms, config.heartbeat_timeout_ms);
+        assert_eq!(defaults.reconnect_delay_ms, config.reconnect_delay_ms);
+    }
+
+    #[test]
+    fn zero_games_plays_forever() {
+        let config = build_config("does-not-exist.conf", false, &[("games", "0")]).unwrap();
+        assert_eq!(None, config.games);
+    }
+
+    #[test]
+    fn file_is_overridden_by_arguments() {
+        let file = write_config_file("snakebot-layered.conf", "host = \"localhost\";\nport = 8080;\n");
+        let config = build_config(&file, true, &[("port", "9090"), ("snake_name", "cli-snake")]).unwrap();
+
+        assert_eq!("localhost", config.host);
+        assert_eq!(9090, config.port);
+        assert_eq!("cli-snake", config.snake_name);
+    }
+
+    #[test]
+    fn missing_required_file_is_an_error() {
+        assert!(build_config("does-not-exist.conf", true, &[]).is_err());
+    }
+
+    #[test]
+    fn venue_follows_the_url() {
+        let url = "wss://proxy.example.com/snake/tournament?token=secret";
+        let config = build_config("does-not-exist.conf", false, &[("url", url)]).unwrap();
+
+        assert_eq!(url, config.connection_url());
+        assert_eq!("tournament", config.venue);
+        let config = build_config("does-not-exist.conf", false, &[("url", "ws://localhost/tournament/")]).unwrap();
+        assert_eq!("tournament", config.venue);
+        assert!(build_config("does-not-exist.conf", false, &[("url", "http://localhost/training")]).is_err());
+    }
+
+    #[test]
+    fn exit_codes_follow_the_cause() {
+        let closed = ClientError::Closed { code: CloseCode::Away, reason: String::new() };
+        assert_eq!(3, exit_code(&closed));
+        assert_eq!(
+            4,
+            exit_code(&ClientError::Disconnected { attempts: 5, cause: Some(Box::new(ClientError::HeartbeatTimeout)) })
+        );
+        assert_eq!(2, exit_code(&ClientError::Disconnected { attempts: 5, cause: None }));
+        let invalid = ClientError::InvalidTlsCaFile { file: "ca.pem".to_string(), cause: "no certificate".into() };
+        assert_eq!(1, exit_code(&invalid));
+    }
+
+    #[test]
+    fn invalid_values_are_reported() {
+        let error = build_config("does-not-exist.conf", false, &[("port", "eighty")]).unwrap_err();
+        assert!(error.to_string().contains("port"));
+
+        let error = build_config("does-not-exist.conf", false, &[("venue", "arena")]).unwrap_err();
+        assert!(error.to_string().contains("arena"));
+
+        let error = build_config("does-not-exist.conf", false, &[("reconnect_attempts", "-1")]).unwrap_err();
+        assert!(error.to_string().contains("reconnect_attempts"));
+    }
 }
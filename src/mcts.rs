@@ -0,0 +1,309 @@
+use crate::{
+    client::Player,
+    simulation::{apply_moves, candidate_moves, default_deadline, default_move, next_map},
+    types::{Direction, GameSettings, Map},
+};
+use log::debug;
+use rand::{rngs::StdRng, seq::SliceRandom, FromEntropy, SeedableRng};
+use std::{collections::HashMap, time::Instant};
+
+const EXPLORATION: f64 = 1.0;
+const DEFAULT_PLAYOUT_DEPTH: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Playout {
+    // Every snake picks any direction but back into its body
+    Random,
+    // Every snake picks at random among the directions it does not die from right away
+    Heuristic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MoveStatistics {
+    pub direction: Direction,
+    pub visits: u32,
+    pub mean_reward: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Arm {
+    visits: u32,
+    total_reward: f64,
+}
+
+struct Node {
+    map: Map,
+    visits: u32,
+    // The moves and their statistics of each snake, indexed like `snake_infos` and empty for dead snakes
+    moves: Vec<Vec<Direction>>,
+    arms: Vec<Vec<Arm>>,
+    children: HashMap<Vec<usize>, usize>,
+}
+
+impl Node {
+    fn new(map: Map) -> Node {
+        let moves: Vec<Vec<Direction>> = map
+            .snake_infos
+            .iter()
+            .map(|snake| if snake.positions.is_empty() { Vec::new() } else { candidate_moves(&map, snake) })
+            .collect();
+        let arms = moves.iter().map(|moves| vec![Arm::default(); moves.len()]).collect();
+        Node { map, visits: 0, moves, arms, children: HashMap::new() }
+    }
+}
+
+// Monte Carlo tree search for simultaneous moves. Every snake picks its own move in each node with UCB1 over its
+// own statistics (decoupled UCT), and new nodes are scored with a playout of a limited number of ticks.
+pub struct MctsBot {
+    settings: GameSettings,
+    playout: Playout,
+    playout_depth: u32,
+    max_iterations: Option<u32>,
+    rng: StdRng,
+    statistics: Vec<MoveStatistics>,
+    iterations: u32,
+}
+
+impl MctsBot {
+    pub fn new() -> MctsBot {
+        MctsBot {
+            settings: GameSettings::default(),
+            playout: Playout::Heuristic,
+            playout_depth: DEFAULT_PLAYOUT_DEPTH,
+            max_iterations: None,
+            rng: StdRng::from_entropy(),
+            statistics: Vec::new(),
+            iterations: 0,
+        }
+    }
+
+    pub fn playout(mut self, playout: Playout, depth: u32) -> MctsBot {
+        self.playout = playout;
+        self.playout_depth = depth;
+        self
+    }
+
+    // Stops after this many iterations even if there is time left
+    pub fn max_iterations(mut self, iterations: u32) -> MctsBot {
+        self.max_iterations = Some(iterations);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> MctsBot {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    // The statistics of our moves at the root of the last search
+    pub fn statistics(&self) -> &[MoveStatistics] {
+        &self.statistics
+    }
+
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    fn search(&mut self, map: &Map, player_id: &str, deadline: Instant) -> Option<Direction> {
+        let own = map.snake_infos.iter().position(|snake| snake.id == player_id)?;
+        let competitive = map.snake_infos.iter().filter(|snake| !snake.positions.is_empty()).count() > 1;
+        let mut nodes = vec![Node::new(map.clone())];
+        self.iterations = 0;
+
+        while Instant::now() < deadline && self.max_iterations.is_none_or(|max| self.iterations < max) {
+            self.iterate(&mut nodes, competitive);
+            self.iterations += 1;
+        }
+
+        let root = &nodes[0];
+        self.statistics = root.moves[own]
+            .iter()
+            .zip(&root.arms[own])
+            .map(|(&direction, arm)| MoveStatistics {
+                direction,
+                visits: arm.visits,
+                mean_reward: if arm.visits > 0 { arm.total_reward / f64::from(arm.visits) } else { 0.0 },
+            })
+            .collect();
+        debug!("{} iterations, {:?}", self.iterations, self.statistics);
+        self.statistics.iter().max_by_key(|statistics| statistics.visits).map(|statistics| statistics.direction)
+    }
+
+    fn iterate(&mut self, nodes: &mut Vec<Node>, competitive: bool) {
+        let mut path: Vec<(usize, Vec<usize>)> = Vec::new();
+        let mut current = 0;
+        let rewards = loop {
+            if is_over(&nodes[current].map, competitive) {
+                break rewards(&nodes[current].map);
+            }
+            let choices = self.select(&nodes[current]);
+            path.push((current, choices.clone()));
+            if let Some(&child) = nodes[current].children.get(&choices) {
+                current = child;
+                continue;
+            }
+
+            let node = &nodes[current];
+            let moves = joint_moves(node, &choices);
+            let (next, _) = next_map(&node.map, &self.settings, &moves);
+            let mut playout = next.clone();
+            nodes.push(Node::new(next));
+            let child = nodes.len() - 1;
+            nodes[current].children.insert(choices, child);
+            nodes[child].visits += 1;
+            break self.run_playout(&mut playout, competitive);
+        };
+
+        for (index, choices) in path {
+            let node = &mut nodes[index];
+            node.visits += 1;
+            for (snake, &choice) in choices.iter().enumerate() {
+                if let Some(arm) = node.arms[snake].get_mut(choice) {
+                    arm.visits += 1;
+                    arm.total_reward += rewards[snake];
+                }
+            }
+        }
+    }
+
+    // Unvisited moves are tried first, then the one with the highest upper confidence bound
+    fn select(&mut self, node: &Node) -> Vec<usize> {
+        let log_visits = f64::from(node.visits.max(1)).ln();
+        node.arms
+            .iter()
+            .map(|arms| {
+                let unvisited: Vec<usize> = (0..arms.len()).filter(|&arm| arms[arm].visits == 0).collect();
+                if let Some(&arm) = unvisited.choose(&mut self.rng) {
+                    return arm;
+                }
+                let bound = |arm: &Arm| {
+                    arm.total_reward / f64::from(arm.visits) + EXPLORATION * (log_visits / f64::from(arm.visits)).sqrt()
+                };
+                (0..arms.len()).max_by(|&a, &b| bound(&arms[a]).partial_cmp(&bound(&arms[b])).unwrap()).unwrap_or(0)
+            })
+            .collect()
+    }
+
+    fn run_playout(&mut self, map: &mut Map, competitive: bool) -> Vec<f64> {
+        for _ in 0..self.playout_depth {
+            if is_over(map, competitive) {
+                break;
+            }
+            let mut moves = HashMap::new();
+            for snake in map.snake_infos.iter().filter(|snake| !snake.positions.is_empty()) {
+                let candidates = match self.playout {
+                    Playout::Random => candidate_moves(map, snake),
+                    Playout::Heuristic => candidate_moves(map, snake)
+                        .into_iter()
+                        .filter(|&direction| map.can_snake_move_in_direction(snake, direction))
+                        .collect(),
+                };
+                let direction = candidates.choose(&mut self.rng).cloned().unwrap_or_else(|| default_move(map, snake));
+                moves.insert(snake.id.clone(), direction);
+            }
+            apply_moves(map, &self.settings, &moves);
+        }
+        rewards(map)
+    }
+}
+
+impl Default for MctsBot {
+    fn default() -> MctsBot {
+        MctsBot::new()
+    }
+}
+
+fn joint_moves(node: &Node, choices: &[usize]) -> HashMap<String, Direction> {
+    node.map
+        .snake_infos
+        .iter()
+        .zip(node.moves.iter().zip(choices))
+        .filter_map(|(snake, (moves, &choice))| moves.get(choice).map(|&direction| (snake.id.clone(), direction)))
+        .collect()
+}
+
+fn is_over(map: &Map, competitive: bool) -> bool {
+    let alive = map.snake_infos.iter().filter(|snake| !snake.positions.is_empty()).count();
+    alive == 0 || (competitive && alive == 1)
+}
+
+// Dead snakes get nothing and the survivors share a bonus, indexed like `snake_infos`
+fn rewards(map: &Map) -> Vec<f64> {
+    let alive = map.snake_infos.iter().filter(|snake| !snake.positions.is_empty()).count();
+    map.snake_infos
+        .iter()
+        .map(|snake| if snake.positions.is_empty() { 0.0 } else { 0.5 + 0.5 / alive as f64 })
+        .collect()
+}
+
+impl Player for MctsBot {
+    fn get_next_move(&mut self, map: &Map, player_id: &str) -> Direction {
+        self.get_next_move_with_deadline(map, player_id, default_deadline(&self.settings))
+    }
+
+    fn get_next_move_with_deadline(&mut self, map: &Map, player_id: &str, deadline: Instant) -> Direction {
+        match map.get_snake_by_id(player_id) {
+            Some(snake) if !snake.positions.is_empty() => {
+                self.search(map, player_id, deadline).unwrap_or_else(|| default_move(map, snake))
+            }
+            _ => Direction::Down,
+        }
+    }
+
+    fn on_game_starting(&mut self, settings: &GameSettings) {
+        self.settings = settings.clone();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test::{far_future, get_dead_end_map};
+    use std::time::Duration;
+
+    fn get_bot(iterations: u32) -> MctsBot {
+        let mut bot = MctsBot::new().seed(5).max_iterations(iterations);
+        bot.on_game_starting(&GameSettings { spontaneous_growth_every_n_world_tick: 0, ..Default::default() });
+        bot
+    }
+
+    #[test]
+    fn dead_ends_are_avoided() {
+        let map = get_dead_end_map();
+        let mut bot = get_bot(500);
+
+        assert_eq!(Direction::Down, bot.get_next_move_with_deadline(&map, "me", far_future()));
+    }
+
+    #[test]
+    fn statistics_cover_every_iteration() {
+        let map = get_dead_end_map();
+        let mut bot = get_bot(300);
+        bot.get_next_move_with_deadline(&map, "me", far_future());
+
+        assert_eq!(300, bot.iterations());
+        assert_eq!(300, bot.statistics().iter().map(|statistics| statistics.visits).sum::<u32>());
+        let best = bot.statistics().iter().find(|statistics| statistics.direction == Direction::Down).unwrap();
+        assert!(bot.statistics().iter().all(|statistics| statistics.mean_reward <= best.mean_reward));
+    }
+
+    #[test]
+    fn search_is_reproducible_with_a_seed() {
+        let map = get_dead_end_map();
+        let (mut first, mut second) = (get_bot(200), get_bot(200));
+        first.get_next_move_with_deadline(&map, "me", far_future());
+        second.get_next_move_with_deadline(&map, "me", far_future());
+
+        assert_eq!(first.statistics(), second.statistics());
+    }
+
+    #[test]
+    fn search_stops_at_the_deadline() {
+        let map = get_dead_end_map();
+        let mut bot = MctsBot::new().playout(Playout::Random, 10);
+        let started = Instant::now();
+
+        bot.get_next_move_with_deadline(&map, "me", started + Duration::from_millis(50));
+        assert!(started.elapsed() < Duration::from_millis(200));
+        assert!(bot.iterations() > 0);
+    }
+}
@@ -0,0 +1,322 @@
+use crate::{
+    client::Player,
+    pathfinding::TieBreak,
+    simulation::{candidate_moves, default_deadline, default_move, next_map},
+    types::{Direction, GameSettings, Map, SnakeInfo},
+    utils::Coordinate,
+};
+use log::debug;
+use std::{collections::HashMap, time::Instant};
+
+const WIN: f64 = 1_000_000.0;
+const DEFAULT_MAX_DEPTH: u32 = 12;
+// Opponents with their heads further away than this follow a fixed policy instead of being searched
+const ADVERSARY_DISTANCE: i32 = 8;
+
+// Scores a map from the point of view of a player that is still alive, higher is better
+pub trait Evaluation {
+    fn evaluate(&self, map: &Map, player_id: &str) -> f64;
+}
+
+impl<F: Fn(&Map, &str) -> f64> Evaluation for F {
+    fn evaluate(&self, map: &Map, player_id: &str) -> f64 {
+        self(map, player_id)
+    }
+}
+
+// Compares the player with the strongest opponent on each weighted measure
+#[derive(Debug, Clone)]
+pub struct WeightedEvaluation {
+    pub territory: f64,
+    pub length: f64,
+    pub points: f64,
+}
+
+impl Default for WeightedEvaluation {
+    fn default() -> WeightedEvaluation {
+        WeightedEvaluation { territory: 1.0, length: 2.0, points: 0.1 }
+    }
+}
+
+impl Evaluation for WeightedEvaluation {
+    fn evaluate(&self, map: &Map, player_id: &str) -> f64 {
+        let territory = if self.territory != 0.0 { map.territory(TieBreak::Nobody, false).tiles } else { Vec::new() };
+        let measure = |index: usize, snake: &SnakeInfo| {
+            self.territory * territory.get(index).cloned().unwrap_or(0) as f64
+                + self.length * snake.positions.len() as f64
+                + self.points * f64::from(snake.points)
+        };
+
+        let mut own = 0.0;
+        let mut strongest_opponent: Option<f64> = None;
+        for (index, snake) in map.snake_infos.iter().enumerate() {
+            if snake.id == player_id {
+                own = measure(index, snake);
+            } else if !snake.positions.is_empty() {
+                let value = measure(index, snake);
+                strongest_opponent = Some(strongest_opponent.map_or(value, |strongest| strongest.max(value)));
+            }
+        }
+        own - strongest_opponent.unwrap_or(0.0)
+    }
+}
+
+// Paranoid alpha-beta search: nearby opponents are assumed to pick the moves that are worst for us, together.
+// Deepens one ply at a time until the deadline and plays the best move of the deepest finished search.
+pub struct MinimaxBot<E = WeightedEvaluation> {
+    evaluation: E,
+    settings: GameSettings,
+    max_depth: u32,
+    last_depth: u32,
+    nodes: u64,
+}
+
+impl MinimaxBot<WeightedEvaluation> {
+    pub fn new() -> MinimaxBot<WeightedEvaluation> {
+        MinimaxBot::with_evaluation(WeightedEvaluation::default())
+    }
+}
+
+impl Default for MinimaxBot<WeightedEvaluation> {
+    fn default() -> MinimaxBot<WeightedEvaluation> {
+        MinimaxBot::new()
+    }
+}
+
+impl<E: Evaluation> MinimaxBot<E> {
+    pub fn with_evaluation(evaluation: E) -> MinimaxBot<E> {
+        MinimaxBot {
+            evaluation,
+            settings: GameSettings::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            last_depth: 0,
+            nodes: 0,
+        }
+    }
+
+    pub fn max_depth(mut self, max_depth: u32) -> MinimaxBot<E> {
+        self.max_depth = max_depth.max(1);
+        self
+    }
+
+    // The depth of the deepest search finished for the last move
+    pub fn last_depth(&self) -> u32 {
+        self.last_depth
+    }
+
+    fn search(&mut self, map: &Map, player_id: &str, deadline: Instant) -> Option<Direction> {
+        let mut best: Option<Direction> = None;
+        self.last_depth = 0;
+        self.nodes = 0;
+        for depth in 1..=self.max_depth {
+            let mut search = Search { bot: self, player_id, deadline, first: best };
+            match search.max_value(map, depth, 0, -f64::INFINITY, f64::INFINITY) {
+                Some((value, direction)) => {
+                    debug!("Depth {} gives {:?} with value {}", depth, direction, value);
+                    best = direction;
+                    self.last_depth = depth;
+                    // Nothing can change a forced win or loss
+                    if value.abs() >= WIN / 2.0 {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        debug!("Searched {} nodes to depth {}", self.nodes, self.last_depth);
+        best
+    }
+}
+
+struct Search<'a, E> {
+    bot: &'a mut MinimaxBot<E>,
+    player_id: &'a str,
+    deadline: Instant,
+    // The best move of the previous iteration, searched first for better pruning
+    first: Option<Direction>,
+}
+
+impl<'a, E: Evaluation> Search<'a, E> {
+    // None when the deadline has passed
+    fn max_value(
+        &mut self,
+        map: &Map,
+        depth: u32,
+        ply: u32,
+        mut alpha: f64,
+        beta: f64,
+    ) -> Option<(f64, Option<Direction>)> {
+        let snake = map.get_snake_by_id(self.player_id)?;
+        let mut directions = candidate_moves(map, snake);
+        if let Some(first) = self.first.take() {
+            if let Some(index) = directions.iter().position(|&direction| direction == first) {
+                directions.remove(index);
+                directions.insert(0, first);
+            }
+        }
+
+        let mut best: (f64, Option<Direction>) = (-f64::INFINITY, None);
+        for direction in directions {
+            let value = self.min_value(map, direction, depth, ply, alpha, beta)?;
+            if value > best.0 {
+                best = (value, Some(direction));
+            }
+            alpha = alpha.max(value);
+            if alpha >= beta {
+                break;
+            }
+        }
+        Some(best)
+    }
+
+    fn min_value(&mut self, map: &Map, own: Direction, depth: u32, ply: u32, alpha: f64, mut beta: f64) -> Option<f64> {
+        let mut best = f64::INFINITY;
+        for mut moves in self.opponent_moves(map) {
+            if Instant::now() >= self.deadline {
+                return None;
+            }
+            self.bot.nodes += 1;
+            moves.insert(self.player_id.to_string(), own);
+            let (next, _) = next_map(map, &self.bot.settings, &moves);
+
+            let value = match self.terminal_value(&next, ply + 1) {
+                Some(value) => value,
+                None if depth <= 1 => self.bot.evaluation.evaluate(&next, self.player_id),
+                None => self.max_value(&next, depth - 1, ply + 1, alpha, beta)?.0,
+            };
+            best = best.min(value);
+            beta = beta.min(value);
+            if alpha >= beta {
+                break;
+            }
+        }
+        Some(best)
+    }
+
+    // Dying later is better than dying sooner, and winning sooner better than winning later
+    fn terminal_value(&self, map: &Map, ply: u32) -> Option<f64> {
+        let alive = map.get_snake_by_id(self.player_id).is_some_and(|snake| !snake.positions.is_empty());
+        let mut opponents = map.snake_infos.iter().filter(|snake| snake.id != self.player_id);
+        if !alive {
+            Some(-WIN + f64::from(ply))
+        } else if map.snake_infos.len() > 1 && opponents.all(|snake| snake.positions.is_empty()) {
+            Some(WIN - f64::from(ply))
+        } else {
+            None
+        }
+    }
+
+    // Every combination of moves of the nearby opponents, the others make their default move
+    fn opponent_moves(&self, map: &Map) -> Vec<HashMap<String, Direction>> {
+        let head = map
+            .get_snake_by_id(self.player_id)
+            .and_then(|snake| snake.positions.first())
+            .map(|&head| Coordinate::from_position(head, map.width));
+        let mut combinations = vec![HashMap::new()];
+        for snake in map.snake_infos.iter().filter(|snake| snake.id != self.player_id && !snake.positions.is_empty()) {
+            let opponent_head = Coordinate::from_position(snake.positions[0], map.width);
+            let directions = match head {
+                Some(head) if head.manhattan_distance_to(opponent_head) <= ADVERSARY_DISTANCE => {
+                    candidate_moves(map, snake)
+                }
+                _ => vec![default_move(map, snake)],
+            };
+            combinations = combinations
+                .into_iter()
+                .flat_map(|moves| {
+                    directions.iter().map(move |&direction| {
+                        let mut moves = moves.clone();
+                        moves.insert(snake.id.clone(), direction);
+                        moves
+                    })
+                })
+                .collect();
+        }
+        combinations
+    }
+}
+
+impl<E: Evaluation> Player for MinimaxBot<E> {
+    fn get_next_move(&mut self, map: &Map, player_id: &str) -> Direction {
+        self.get_next_move_with_deadline(map, player_id, default_deadline(&self.settings))
+    }
+
+    fn get_next_move_with_deadline(&mut self, map: &Map, player_id: &str, deadline: Instant) -> Direction {
+        match map.get_snake_by_id(player_id) {
+            Some(snake) if !snake.positions.is_empty() => {
+                self.search(map, player_id, deadline).unwrap_or_else(|| default_move(map, snake))
+            }
+            _ => Direction::Down,
+        }
+    }
+
+    fn on_game_starting(&mut self, settings: &GameSettings) {
+        self.settings = settings.clone();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test::{far_future, get_dead_end_map, get_map};
+    use std::time::Duration;
+
+    const MAP_WIDTH: i32 = 6;
+
+    fn get_bot() -> MinimaxBot {
+        let mut bot = MinimaxBot::new().max_depth(4);
+        bot.on_game_starting(&GameSettings { spontaneous_growth_every_n_world_tick: 0, ..Default::default() });
+        bot
+    }
+
+    #[test]
+    fn dead_ends_are_avoided() {
+        let map = get_dead_end_map();
+        let mut bot = get_bot();
+
+        assert_eq!(Direction::Down, bot.get_next_move_with_deadline(&map, "me", far_future()));
+        assert_eq!(4, bot.last_depth());
+    }
+
+    // Moving right risks a head-on collision that kills both snakes
+    //yx012345
+    //0
+    //1
+    //2 1 2
+    #[test]
+    fn head_on_collisions_are_avoided() {
+        let map = get_map(
+            MAP_WIDTH,
+            MAP_WIDTH,
+            &[("me", &[Coordinate(0, 2), Coordinate(0, 1)]), ("other", &[Coordinate(2, 2), Coordinate(3, 2)])],
+            &[],
+            &[],
+        );
+        let mut bot = get_bot();
+
+        assert_eq!(Direction::Down, bot.get_next_move_with_deadline(&map, "me", far_future()));
+    }
+
+    #[test]
+    fn search_stops_at_the_deadline() {
+        let map =
+            get_map(MAP_WIDTH, MAP_WIDTH, &[("me", &[Coordinate(0, 0)]), ("other", &[Coordinate(5, 5)])], &[], &[]);
+        let mut bot = MinimaxBot::new().max_depth(100);
+        let started = Instant::now();
+
+        bot.get_next_move_with_deadline(&map, "me", started + Duration::from_millis(50));
+        assert!(started.elapsed() < Duration::from_millis(200));
+        assert!(bot.last_depth() < 100);
+    }
+
+    #[test]
+    fn evaluation_can_be_replaced() {
+        let map =
+            get_map(MAP_WIDTH, MAP_WIDTH, &[("me", &[Coordinate(2, 2)]), ("other", &[Coordinate(5, 5)])], &[], &[]);
+        // Prefers being as far up as possible
+        let evaluation = |map: &Map, id: &str| -f64::from(map.get_snake_by_id(id).unwrap().positions[0] / map.width);
+        let mut bot = MinimaxBot::with_evaluation(evaluation).max_depth(1);
+
+        assert_eq!(Direction::Up, bot.get_next_move_with_deadline(&map, "me", far_future()));
+    }
+}
@@ -0,0 +1,398 @@
+use crate::{
+    types::{Direction, Map, SnakeInfo},
+    utils::{Coordinate, DIRECTIONS},
+};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, VecDeque},
+};
+
+const NEVER: u32 = u32::MAX;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    // Every step from the start, the start itself is not included
+    pub directions: Vec<Direction>,
+    pub coordinates: Vec<Coordinate>,
+}
+
+impl Path {
+    pub fn first_direction(&self) -> Option<Direction> {
+        self.directions.first().cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.directions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directions.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReachableArea {
+    pub tiles: usize,
+    pub tail_reachable: bool,
+}
+
+// Who gets a tile that several snakes reach at the same time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TieBreak {
+    // Nobody owns it, like a head-on collision that kills both snakes
+    Nobody,
+    // The longest snake owns it, or nobody if they are equally long
+    Longest,
+    // The snake at this index in `snake_infos` owns it if it is one of those reaching it
+    Favour(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Territory {
+    // The index in `snake_infos` of the snake reaching each tile first, indexed by position
+    pub owners: Vec<Option<usize>>,
+    // The number of tiles owned by each snake, indexed like `snake_infos`
+    pub tiles: Vec<usize>,
+    // The number of tiles reached at the same time by several snakes and owned by nobody
+    pub contested: usize,
+}
+
+// The number of steps after which each tile can be entered, indexed by position
+struct Blockers {
+    width: i32,
+    free_after: Vec<u32>,
+}
+
+impl Blockers {
+    // With moving tails a body part can be entered once the snake has moved past it, assuming no snake grows
+    // on the way
+    fn new(map: &Map, tails_move: bool) -> Blockers {
+        let mut free_after = vec![0; (map.width * map.height) as usize];
+        for &position in &map.obstacle_positions {
+            free_after[position as usize] = NEVER;
+        }
+        for snake in &map.snake_infos {
+            let length = snake.positions.len() as u32;
+            for (index, &position) in snake.positions.iter().enumerate() {
+                // Segments stacked on the tail after eating leave together with the last of them
+                let free = &mut free_after[position as usize];
+                *free = (*free).max(if tails_move { length - index as u32 } else { NEVER });
+            }
+        }
+        Blockers { width: map.width, free_after }
+    }
+
+    fn can_enter(&self, map: &Map, coordinate: Coordinate, steps: u32) -> bool {
+        map.inside_map(coordinate) && self.free_after[coordinate.to_position(self.width) as usize] <= steps
+    }
+}
+
+impl Map {
+    // The number of steps needed to reach every tile from `from`, indexed by position
+    pub fn distance_map(&self, from: Coordinate, tails_move: bool) -> Vec<Option<u32>> {
+        let blockers = Blockers::new(self, tails_move);
+        let mut distances = vec![None; (self.width * self.height) as usize];
+        let mut queue = VecDeque::new();
+        if self.inside_map(from) {
+            distances[from.to_position(self.width) as usize] = Some(0);
+            queue.push_back((from, 0));
+        }
+
+        while let Some((coordinate, steps)) = queue.pop_front() {
+            for &direction in &DIRECTIONS {
+                let next = coordinate + direction.to_movement_delta();
+                if blockers.can_enter(self, next, steps + 1) {
+                    let distance = &mut distances[next.to_position(self.width) as usize];
+                    if distance.is_none() {
+                        *distance = Some(steps + 1);
+                        queue.push_back((next, steps + 1));
+                    }
+                }
+            }
+        }
+        distances
+    }
+
+    pub fn distance_between(&self, from: Coordinate, to: Coordinate, tails_move: bool) -> Option<u32> {
+        self.shortest_path(from, to, tails_move).map(|path| path.len() as u32)
+    }
+
+    // A* search with the manhattan distance as heuristic
+    pub fn shortest_path(&self, from: Coordinate, to: Coordinate, tails_move: bool) -> Option<Path> {
+        if !self.inside_map(from) || !self.inside_map(to) {
+            return None;
+        }
+        let blockers = Blockers::new(self, tails_move);
+        let size = (self.width * self.height) as usize;
+        let mut steps = vec![NEVER; size];
+        let mut came_from: Vec<Option<Direction>> = vec![None; size];
+        let mut open = BinaryHeap::new();
+
+        steps[from.to_position(self.width) as usize] = 0;
+        open.push(Reverse((from.manhattan_distance_to(to) as u32, 0, from.to_position(self.width))));
+
+        while let Some(Reverse((_, current_steps, position))) = open.pop() {
+            let coordinate = Coordinate::from_position(position, self.width);
+            if coordinate == to {
+                return Some(self.trace_path(from, to, &came_from));
+            }
+            if current_steps > steps[position as usize] {
+                continue;
+            }
+            for &direction in &DIRECTIONS {
+                let next = coordinate + direction.to_movement_delta();
+                let next_steps = current_steps + 1;
+                if blockers.can_enter(self, next, next_steps) {
+                    let next_position = next.to_position(self.width);
+                    if next_steps < steps[next_position as usize] {
+                        steps[next_position as usize] = next_steps;
+                        came_from[next_position as usize] = Some(direction);
+                        let estimate = next_steps + next.manhattan_distance_to(to) as u32;
+                        open.push(Reverse((estimate, next_steps, next_position)));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // Breadth first search for the closest food, ties are broken by the order of the food positions
+    pub fn path_to_nearest_food(&self, from: Coordinate, tails_move: bool) -> Option<Path> {
+        let distances = self.distance_map(from, tails_move);
+        let food = self
+            .food_positions
+            .iter()
+            .filter_map(|&position| distances[position as usize].map(|distance| (distance, position)))
+            .min_by_key(|&(distance, _)| distance)?;
+        self.shortest_path(from, Coordinate::from_position(food.1, self.width), tails_move)
+    }
+
+    // Flood fills the free tiles reachable from `from`, which is counted itself if it is free
+    pub fn reachable_area(&self, from: Coordinate, snake: &SnakeInfo) -> ReachableArea {
+        if !self.is_tile_available_for_movement(from) {
+            return ReachableArea { tiles: 0, tail_reachable: false };
+        }
+        let distances = self.distance_map(from, false);
+        let tail_reachable = snake.positions.last().is_some_and(|&tail| {
+            let tail = Coordinate::from_position(tail, self.width);
+            DIRECTIONS.iter().map(|direction| tail + direction.to_movement_delta()).any(|neighbour| {
+                self.inside_map(neighbour) && distances[neighbour.to_position(self.width) as usize].is_some()
+            })
+        });
+        ReachableArea { tiles: distances.iter().filter(|distance| distance.is_some()).count(), tail_reachable }
+    }
+
+    // The area the snake could reach after moving in `direction`, or None if the move kills it
+    pub fn reachable_area_in_direction(&self, snake: &SnakeInfo, direction: Direction) -> Option<ReachableArea> {
+        let head = Coordinate::from_position(*snake.positions.first()?, self.width);
+        if self.can_snake_move_in_direction(snake, direction) {
+            Some(self.reachable_area(head + direction.to_movement_delta(), snake))
+        } else {
+            None
+        }
+    }
+
+    // Breadth first search from all heads at once, contested tiles owned by nobody are not expanded further
+    pub fn territory(&self, tie_break: TieBreak, tails_move: bool) -> Territory {
+        let blockers = Blockers::new(self, tails_move);
+        let mut reached = vec![false; (self.width * self.height) as usize];
+        let mut territory =
+            Territory { owners: vec![None; reached.len()], tiles: vec![0; self.snake_infos.len()], contested: 0 };
+        let mut frontier: Vec<(Coordinate, usize)> = Vec::new();
+        for (index, snake) in self.snake_infos.iter().enumerate() {
+            if let Some(&head) = snake.positions.first() {
+                reached[head as usize] = true;
+                frontier.push((Coordinate::from_position(head, self.width), index));
+            }
+        }
+
+        let mut steps = 0;
+        while !frontier.is_empty() {
+            steps += 1;
+            let mut claims: BTreeMap<i32, Vec<usize>> = BTreeMap::new();
+            for &(coordinate, owner) in &frontier {
+                for &direction in &DIRECTIONS {
+                    let next = coordinate + direction.to_movement_delta();
+                    if blockers.can_enter(self, next, steps) && !reached[next.to_position(self.width) as usize] {
+                        let claimants = claims.entry(next.to_position(self.width)).or_default();
+                        if !claimants.contains(&owner) {
+                            claimants.push(owner);
+                        }
+                    }
+                }
+            }
+
+            frontier.clear();
+            for (position, claimants) in claims {
+                reached[position as usize] = true;
+                match self.resolve_tie(&claimants, tie_break) {
+                    Some(owner) => {
+                        territory.owners[position as usize] = Some(owner);
+                        territory.tiles[owner] += 1;
+                        frontier.push((Coordinate::from_position(position, self.width), owner));
+                    }
+                    None => territory.contested += 1,
+                }
+            }
+        }
+        territory
+    }
+
+    fn resolve_tie(&self, claimants: &[usize], tie_break: TieBreak) -> Option<usize> {
+        match (claimants, tie_break) {
+            (&[owner], _) => Some(owner),
+            (_, TieBreak::Nobody) => None,
+            (_, TieBreak::Favour(favoured)) => claimants.iter().cloned().find(|&owner| owner == favoured),
+            (_, TieBreak::Longest) => {
+                let length = |&owner: &usize| self.snake_infos[owner].positions.len();
+                let longest = claimants.iter().map(length).max()?;
+                let mut longest_claimants = claimants.iter().filter(|owner| length(owner) == longest);
+                match (longest_claimants.next(), longest_claimants.next()) {
+                    (Some(&owner), None) => Some(owner),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    fn trace_path(&self, from: Coordinate, to: Coordinate, came_from: &[Option<Direction>]) -> Path {
+        let mut directions = Vec::new();
+        let mut coordinates = Vec::new();
+        let mut coordinate = to;
+        while coordinate != from {
+            let direction = came_from[coordinate.to_position(self.width) as usize].unwrap();
+            directions.push(direction);
+            coordinates.push(coordinate);
+            let Coordinate(dx, dy) = direction.to_movement_delta();
+            coordinate = coordinate + Coordinate(-dx, -dy);
+        }
+        directions.reverse();
+        coordinates.reverse();
+        Path { directions, coordinates }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        board::Board,
+        utils::test::{get_map, get_pocket_map},
+    };
+
+    const MAP_WIDTH: i32 = 5;
+
+    fn get_test_map() -> Map {
+        get_pocket_map(&[])
+    }
+
+    #[test]
+    fn shortest_path_goes_around_obstacles() {
+        let map = get_test_map();
+        let path = map.shortest_path(Coordinate(2, 0), Coordinate(2, 3), false).unwrap();
+
+        assert_eq!(9, path.len());
+        assert_eq!(Some(Direction::Right), path.first_direction());
+        assert_eq!(Some(&Coordinate(2, 3)), path.coordinates.last());
+    }
+
+    #[test]
+    fn shortest_path_is_none_when_blocked() {
+        let mut map = get_test_map();
+        map.obstacle_positions.push(Coordinate(4, 1).to_position(MAP_WIDTH));
+
+        assert_eq!(None, map.shortest_path(Coordinate(0, 0), Coordinate(4, 4), false));
+        assert_eq!(None, map.shortest_path(Coordinate(0, 0), Coordinate(5, 0), false));
+    }
+
+    #[test]
+    fn moving_tails_open_up_shorter_paths() {
+        let map = get_test_map();
+
+        assert_eq!(Some(12), map.distance_between(Coordinate(1, 3), Coordinate(1, 1), false));
+        // The tail at (2, 2) has moved away after one step and (1, 2) after two
+        assert_eq!(Some(4), map.distance_between(Coordinate(1, 3), Coordinate(1, 1), true));
+    }
+
+    #[test]
+    fn stacked_tails_stay_until_the_last_segment_moves() {
+        let map = get_map(3, 1, &[("1", &[Coordinate(1, 0), Coordinate(1, 0), Coordinate(1, 0)])], &[], &[]);
+
+        assert_eq!(None, map.distance_between(Coordinate(0, 0), Coordinate(2, 0), true));
+        assert_eq!(Some(3), Board::new(&map).ticks_until_free(Coordinate(1, 0)));
+    }
+
+    #[test]
+    fn distance_map_counts_steps() {
+        let map = get_test_map();
+        let distances = map.distance_map(Coordinate(0, 0), false);
+
+        assert_eq!(Some(0), distances[Coordinate(0, 0).to_position(MAP_WIDTH) as usize]);
+        assert_eq!(Some(2), distances[Coordinate(2, 0).to_position(MAP_WIDTH) as usize]);
+        assert_eq!(Some(11), distances[Coordinate(2, 3).to_position(MAP_WIDTH) as usize]);
+        assert_eq!(None, distances[Coordinate(2, 1).to_position(MAP_WIDTH) as usize]);
+    }
+
+    #[test]
+    fn nearest_food_is_found() {
+        let map = get_test_map();
+        let path = map.path_to_nearest_food(Coordinate(0, 0), false).unwrap();
+
+        assert_eq!(vec![Direction::Right, Direction::Right], path.directions);
+    }
+
+    // Two snakes facing each other on a 5 by 3 map, the second one is longer
+    //yx01234
+    //0     2
+    //1 1   2
+    //2
+    fn get_territory_map() -> Map {
+        get_map(MAP_WIDTH, 3, &[("1", &[Coordinate(0, 1)]), ("2", &[Coordinate(4, 1), Coordinate(4, 0)])], &[], &[])
+    }
+
+    #[test]
+    fn territory_is_split_between_snakes() {
+        let map = get_territory_map();
+        let territory = map.territory(TieBreak::Nobody, false);
+
+        assert_eq!(vec![5, 4], territory.tiles);
+        assert_eq!(3, territory.contested);
+        assert_eq!(Some(0), territory.owners[Coordinate(1, 2).to_position(MAP_WIDTH) as usize]);
+        assert_eq!(None, territory.owners[Coordinate(2, 2).to_position(MAP_WIDTH) as usize]);
+        assert_eq!(Some(1), territory.owners[Coordinate(3, 0).to_position(MAP_WIDTH) as usize]);
+    }
+
+    #[test]
+    fn territory_ties_are_broken() {
+        let map = get_territory_map();
+
+        let territory = map.territory(TieBreak::Longest, false);
+        assert_eq!((vec![5, 7], 0), (territory.tiles, territory.contested));
+
+        let territory = map.territory(TieBreak::Favour(0), false);
+        assert_eq!((vec![8, 4], 0), (territory.tiles, territory.contested));
+    }
+
+    #[test]
+    fn reachable_area_is_flood_filled() {
+        let map = get_test_map();
+        let snake = map.get_snake_by_id("1").unwrap();
+
+        assert_eq!(
+            Some(ReachableArea { tiles: 17, tail_reachable: true }),
+            map.reachable_area_in_direction(snake, Direction::Right)
+        );
+        assert_eq!(None, map.reachable_area_in_direction(snake, Direction::Down));
+        assert_eq!(None, map.reachable_area_in_direction(snake, Direction::Up));
+    }
+
+    #[test]
+    fn small_pockets_are_detected() {
+        let mut map = get_test_map();
+        map.obstacle_positions.push(Coordinate(4, 1).to_position(MAP_WIDTH));
+        let snake = map.get_snake_by_id("1").unwrap();
+
+        assert_eq!(ReachableArea { tiles: 6, tail_reachable: false }, map.reachable_area(Coordinate(1, 0), snake));
+        assert_eq!(ReachableArea { tiles: 0, tail_reachable: false }, map.reachable_area(Coordinate(2, 1), snake));
+    }
+}
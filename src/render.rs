@@ -0,0 +1,155 @@
+use crate::{
+    board::{Board, Cell},
+    pathfinding::Path,
+    types::Map,
+    utils::Coordinate,
+};
+use std::fmt;
+
+const WALL: char = '#';
+const OBSTACLE: char = 'X';
+const FOOD: char = 'F';
+const EMPTY: char = '.';
+const PATH: char = '*';
+
+// ANSI foreground colours, the snakes take turns using SNAKE_COLORS
+const SNAKE_COLORS: [u8; 5] = [32, 34, 35, 36, 31];
+const FOOD_COLOR: u8 = 33;
+const OBSTACLE_COLOR: u8 = 90;
+const OVERLAY_COLOR: u8 = 97;
+
+// Draws a map as text with a wall around it. Snake heads are upper case letters and their bodies the same letter in
+// lower case, A for the first snake in `snake_infos`, B for the second and so on.
+pub struct Renderer<'a> {
+    board: Board<'a>,
+    colors: bool,
+    overlays: Vec<(char, Vec<Coordinate>)>,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(map: &'a Map) -> Renderer<'a> {
+        Renderer { board: Board::new(map), colors: false, overlays: Vec::new() }
+    }
+
+    // Use ANSI escape codes, for terminals but not for log files
+    pub fn colors(mut self, colors: bool) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    // Marks the free tiles among `coordinates` with `mark`, later overlays are drawn on top of earlier ones
+    pub fn overlay<I: IntoIterator<Item = Coordinate>>(mut self, mark: char, coordinates: I) -> Self {
+        self.overlays.push((mark, coordinates.into_iter().collect()));
+        self
+    }
+
+    pub fn path(self, path: &Path) -> Self {
+        self.overlay(PATH, path.coordinates.iter().cloned())
+    }
+
+    fn symbol_at(&self, coordinate: Coordinate) -> (char, Option<u8>) {
+        let cell = self.board.cell_at(coordinate);
+        if let Cell::Empty | Cell::Food = cell {
+            let overlay = self.overlays.iter().rev().find(|(_, coordinates)| coordinates.contains(&coordinate));
+            if let Some(&(mark, _)) = overlay {
+                return (mark, Some(OVERLAY_COLOR));
+            }
+        }
+        match cell {
+            Cell::Wall => (WALL, None),
+            Cell::Empty => (EMPTY, None),
+            Cell::Food => (FOOD, Some(FOOD_COLOR)),
+            Cell::Obstacle => (OBSTACLE, Some(OBSTACLE_COLOR)),
+            Cell::Snake { owner, age } => {
+                let letter = if age == 0 { snake_letter(owner) } else { snake_letter(owner).to_ascii_lowercase() };
+                (letter, Some(SNAKE_COLORS[owner % SNAKE_COLORS.len()]))
+            }
+        }
+    }
+}
+
+// The letter of the snake at index `owner` in `snake_infos`
+pub fn snake_letter(owner: usize) -> char {
+    (b'A' + (owner % 26) as u8) as char
+}
+
+impl<'a> fmt::Display for Renderer<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let map = self.board.map();
+        for y in -1..=map.height {
+            for x in -1..=map.width {
+                match self.symbol_at(Coordinate(x, y)) {
+                    (symbol, Some(color)) if self.colors => write!(f, "\x1b[{}m{}\x1b[0m", color, symbol)?,
+                    (symbol, _) => write!(f, "{}", symbol)?,
+                }
+            }
+            if y < map.height {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Map {
+    pub fn render(&self) -> Renderer<'_> {
+        Renderer::new(self)
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.render().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test::get_map;
+
+    const MAP_WIDTH: i32 = 4;
+
+    fn get_test_map() -> Map {
+        get_map(
+            MAP_WIDTH,
+            3,
+            &[
+                ("1", &[Coordinate(3, 2), Coordinate(3, 1), Coordinate(3, 0), Coordinate(2, 0)]),
+                ("2", &[Coordinate(0, 2)]),
+            ],
+            &[Coordinate(0, 0)],
+            &[Coordinate(2, 1)],
+        )
+    }
+
+    #[test]
+    fn map_is_drawn_inside_walls() {
+        let expected = "######\n#F.aa#\n#..Xa#\n#B..A#\n######";
+        assert_eq!(expected, get_test_map().to_string());
+    }
+
+    #[test]
+    fn overlays_only_cover_free_tiles() {
+        let map = get_test_map();
+        let path = map.shortest_path(Coordinate(0, 2), Coordinate(0, 0), false).unwrap();
+        let rendered = map.render().overlay('+', vec![Coordinate(1, 1), Coordinate(3, 1)]).path(&path).to_string();
+
+        assert_eq!("######\n#*.aa#\n#*+Xa#\n#B..A#\n######", rendered);
+    }
+
+    #[test]
+    fn colors_are_escape_codes() {
+        let rendered = get_test_map().render().colors(true).to_string();
+
+        assert!(rendered.starts_with("######\n#\x1b[33mF\x1b[0m.\x1b[32ma"));
+        assert!(rendered.contains("\x1b[34mB\x1b[0m"));
+    }
+
+    #[test]
+    fn stacked_snake_shows_its_head() {
+        let map = get_map(2, 1, &[("1", &[Coordinate(0, 0), Coordinate(0, 0)])], &[], &[]);
+
+        assert_eq!("####\n#A.#\n####", map.to_string());
+    }
+}
@@ -0,0 +1,316 @@
+use crate::{
+    client::{notify, Player},
+    types::{Direction, InboundMessage},
+};
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const REPLAY_EXTENSION: &str = "jsonl";
+const REGISTER_MOVE_TYPE: &str = "se.cygni.snake.api.request.RegisterMove";
+const TIME_PER_MOVE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    Server,
+    Client,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayEntry {
+    pub timestamp_ms: u64,
+    pub origin: Origin,
+    // The message exactly as it was sent
+    pub message: Value,
+}
+
+// Writes every message to `<directory>/<game_id>.jsonl`, one entry per line. Messages sent before the game id is
+// known, like the registration, are held back and written to the file of the first game. Game ids can repeat across
+// runs, so a file left from an earlier run is overwritten the first time a recorder opens it.
+pub struct Recorder {
+    directory: PathBuf,
+    pending: Vec<ReplayEntry>,
+    game: Option<(String, BufWriter<File>)>,
+    opened: HashSet<String>,
+}
+
+impl Recorder {
+    pub fn new<P: AsRef<Path>>(directory: P) -> io::Result<Recorder> {
+        fs::create_dir_all(&directory)?;
+        Ok(Recorder {
+            directory: directory.as_ref().to_path_buf(),
+            pending: Vec::new(),
+            game: None,
+            opened: HashSet::new(),
+        })
+    }
+
+    pub fn path_for(&self, game_id: &str) -> io::Result<PathBuf> {
+        replay_path(&self.directory, game_id)
+    }
+
+    pub fn record(&mut self, origin: Origin, text: &str) -> io::Result<()> {
+        let message: Value = serde_json::from_str(text)?;
+        let game_id = message.get("gameId").and_then(Value::as_str).filter(|id| !id.is_empty()).map(str::to_string);
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64);
+        self.pending.push(ReplayEntry { timestamp_ms, origin, message });
+
+        if let Some(game_id) = game_id {
+            if self.game.as_ref().is_none_or(|(current, _)| *current != game_id) {
+                let path = match self.path_for(&game_id) {
+                    Ok(path) => path,
+                    Err(error) => {
+                        // Nothing is kept for a game that can not be written, or it would pile up
+                        self.pending.clear();
+                        self.game = None;
+                        return Err(error);
+                    }
+                };
+                let mut options = OpenOptions::new();
+                if self.opened.insert(game_id.clone()) {
+                    options.write(true).truncate(true);
+                } else {
+                    options.append(true);
+                }
+                let file = options.create(true).open(path)?;
+                self.game = Some((game_id, BufWriter::new(file)));
+            }
+        }
+        if let Some((_, ref mut writer)) = self.game {
+            for entry in self.pending.drain(..) {
+                serde_json::to_writer(&mut *writer, &entry)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    // Recording is a debugging aid, so failures are only logged
+    pub fn record_or_warn(&mut self, origin: Origin, text: &str) {
+        if let Err(error) = self.record(origin, text) {
+            warn!("Failed to record message to replay: {}", error);
+        }
+    }
+}
+
+// The game id comes from the server, so anything but a plain file name is refused rather than joined onto the directory
+pub fn replay_path<P: AsRef<Path>>(directory: P, game_id: &str) -> io::Result<PathBuf> {
+    let mut components = Path::new(game_id).components();
+    let plain = match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) => name == game_id && !game_id.contains(['/', '\\']),
+        _ => false,
+    };
+    if !plain {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} is not a valid game id", game_id)));
+    }
+    Ok(directory.as_ref().join(format!("{}.{}", game_id, REPLAY_EXTENSION)))
+}
+
+pub fn read_replay<P: AsRef<Path>>(path: P) -> io::Result<Vec<ReplayEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    reader
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+// The messages the server sent, in order, skipping those that can not be read
+pub fn inbound_messages(entries: &[ReplayEntry]) -> Vec<InboundMessage> {
+    entries
+        .iter()
+        .filter(|entry| entry.origin == Origin::Server)
+        .filter_map(|entry| match InboundMessage::from_value(entry.message.clone()) {
+            Ok(message) => Some(message),
+            Err(error) => {
+                warn!("Skipping unreadable message in replay: {}", error);
+                None
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub game_tick: u32,
+    // The move sent during the game, None if it was never sent
+    pub recorded: Option<Direction>,
+    pub replayed: Direction,
+}
+
+impl Decision {
+    pub fn differs(&self) -> bool {
+        self.recorded != Some(self.replayed)
+    }
+}
+
+// Feeds the server messages of a replay to the player and pairs each move it makes with the one recorded for the tick.
+// The player gets a minute per move, so the result does not depend on the speed of the machine as long as its search
+// stops at a budget of its own. Only players that decide the same way every time, like a seeded `MctsBot` with
+// `max_iterations` or a `MinimaxBot` with `max_depth`, give the same decisions on every run.
+pub fn replay_decisions<P: Player + ?Sized>(entries: &[ReplayEntry], player: &mut P) -> Vec<Decision> {
+    let mut recorded = HashMap::new();
+    for entry in entries.iter().filter(|entry| entry.origin == Origin::Client) {
+        if entry.message["type"] == REGISTER_MOVE_TYPE {
+            let game_tick = entry.message["gameTick"].as_u64().map(|tick| tick as u32);
+            let direction = serde_json::from_value::<Direction>(entry.message["direction"].clone()).ok();
+            if let (Some(game_tick), Some(direction)) = (game_tick, direction) {
+                // A late answer from the player is ignored by the server, so the first move counts
+                recorded.entry(game_tick).or_insert(direction);
+            }
+        }
+    }
+
+    let mut decisions = Vec::new();
+    for message in inbound_messages(entries) {
+        notify(player, &message);
+        if let InboundMessage::MapUpdate { map, game_tick, receiving_player_id, .. } = message {
+            let replayed =
+                player.get_next_move_with_deadline(&map, &receiving_player_id, Instant::now() + TIME_PER_MOVE);
+            decisions.push(Decision { game_tick, recorded: recorded.get(&game_tick).cloned(), replayed });
+        }
+    }
+    decisions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{mcts::MctsBot, types::Map};
+    use serde_json::json;
+    use std::env;
+
+    struct DownBot;
+
+    impl Player for DownBot {
+        fn get_next_move(&mut self, _: &Map, _: &str) -> Direction {
+            Direction::Down
+        }
+    }
+
+    fn entry(origin: Origin, message: Value) -> ReplayEntry {
+        ReplayEntry { timestamp_ms: 0, origin, message }
+    }
+
+    fn map_update(game_tick: u32) -> ReplayEntry {
+        let map = json!({
+            "width": 3, "height": 3, "worldTick": game_tick, "foodPositions": [], "obstaclePositions": [],
+            "snakeInfos": [{"id": "me", "name": "me", "points": 0, "positions": [4], "tailProtectedForGameTicks": 0}]
+        });
+        entry(
+            Origin::Server,
+            json!({"type": "se.cygni.snake.api.event.MapUpdateEvent", "receivingPlayerId": "me",
+                   "gameId": "game", "gameTick": game_tick, "map": map}),
+        )
+    }
+
+    fn register_move(game_tick: u32, direction: &str) -> ReplayEntry {
+        entry(
+            Origin::Client,
+            json!({"type": REGISTER_MOVE_TYPE, "receivingPlayerId": "me", "gameId": "game",
+                   "gameTick": game_tick, "direction": direction}),
+        )
+    }
+
+    #[test]
+    fn replayed_decisions_are_compared_per_tick() {
+        let entries = vec![
+            map_update(0),
+            register_move(0, "DOWN"),
+            map_update(1),
+            register_move(1, "UP"),
+            register_move(1, "DOWN"),
+            map_update(2),
+            entry(Origin::Server, json!({"type": "se.cygni.snake.api.event.Unknown"})),
+        ];
+        let decisions = replay_decisions(&entries, &mut DownBot);
+
+        assert_eq!(
+            vec![
+                Decision { game_tick: 0, recorded: Some(Direction::Down), replayed: Direction::Down },
+                Decision { game_tick: 1, recorded: Some(Direction::Up), replayed: Direction::Down },
+                Decision { game_tick: 2, recorded: None, replayed: Direction::Down },
+            ],
+            decisions
+        );
+        assert_eq!(vec![false, true, true], decisions.iter().map(Decision::differs).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn budgeted_searches_replay_the_same_way() {
+        let entries: Vec<_> = (0..3).map(map_update).collect();
+        let replay = || replay_decisions(&entries, &mut MctsBot::new().seed(3).max_iterations(200));
+
+        assert_eq!(replay(), replay());
+    }
+
+    #[test]
+    fn messages_are_recorded_per_game() {
+        let directory = env::temp_dir().join("snakebot-replays-per-game");
+        let _ = fs::remove_dir_all(&directory);
+        let mut recorder = Recorder::new(&directory).unwrap();
+
+        recorder.record(Origin::Client, r#"{"type":"RegisterPlayer","playerName":"snake"}"#).unwrap();
+        recorder.record(Origin::Server, r#"{"type":"PlayerRegistered","gameId":""}"#).unwrap();
+        recorder.record(Origin::Server, r#"{"type":"GameStarting","gameId":"first"}"#).unwrap();
+        recorder.record(Origin::Client, r#"{"type":"HeartBeatRequest"}"#).unwrap();
+        recorder.record(Origin::Server, r#"{"type":"GameStarting","gameId":"second"}"#).unwrap();
+
+        let first = read_replay(replay_path(&directory, "first").unwrap()).unwrap();
+        let types: Vec<_> = first.iter().map(|entry| entry.message["type"].as_str().unwrap()).collect();
+        assert_eq!(vec!["RegisterPlayer", "PlayerRegistered", "GameStarting", "HeartBeatRequest"], types);
+        assert_eq!(Origin::Client, first[0].origin);
+        assert!(first.windows(2).all(|pair| pair[0].timestamp_ms <= pair[1].timestamp_ms));
+        assert_eq!(1, read_replay(replay_path(&directory, "second").unwrap()).unwrap().len());
+    }
+
+    #[test]
+    fn replays_from_earlier_runs_are_overwritten() {
+        let directory = env::temp_dir().join("snakebot-replays-earlier-runs");
+        let _ = fs::remove_dir_all(&directory);
+        let path = replay_path(&directory, "game").unwrap();
+        for _ in 0..2 {
+            let mut recorder = Recorder::new(&directory).unwrap();
+            recorder.record(Origin::Server, r#"{"type":"GameStarting","gameId":"game"}"#).unwrap();
+            recorder.record(Origin::Server, r#"{"type":"GameStarting","gameId":"other"}"#).unwrap();
+            recorder.record(Origin::Server, r#"{"type":"GameEnded","gameId":"game"}"#).unwrap();
+        }
+
+        let types: Vec<_> = read_replay(&path).unwrap().iter().map(|entry| entry.message["type"].clone()).collect();
+        assert_eq!(vec!["GameStarting", "GameEnded"], types);
+    }
+
+    #[test]
+    fn game_ids_must_be_plain_file_names() {
+        let directory = env::temp_dir().join("snakebot-replays-game-ids");
+        let mut recorder = Recorder::new(&directory).unwrap();
+
+        assert_eq!(directory.join("a1-b2.jsonl"), recorder.path_for("a1-b2").unwrap());
+        assert_eq!(directory.join("v1.2.jsonl"), recorder.path_for("v1.2").unwrap());
+        for game_id in &["..", ".", "../escaped", "/tmp/escaped", "nested/game", "nested\\game", "./game"] {
+            assert!(recorder.path_for(game_id).is_err(), "{}", game_id);
+        }
+        let message = r#"{"type":"GameStarting","gameId":"../escaped"}"#;
+        assert_eq!(io::ErrorKind::InvalidInput, recorder.record(Origin::Server, message).unwrap_err().kind());
+        assert!(!directory.parent().unwrap().join("escaped.jsonl").exists());
+        assert!(recorder.pending.is_empty());
+    }
+
+    #[test]
+    fn invalid_messages_are_an_error() {
+        let directory = env::temp_dir().join("snakebot-replays-invalid");
+        let mut recorder = Recorder::new(&directory).unwrap();
+
+        assert!(recorder.record(Origin::Server, "not json").is_err());
+    }
+}
@@ -0,0 +1,657 @@
+use crate::{
+    bots::SafeBot,
+    client::{notify, Player},
+    engine::{Game, GameOptions},
+    types::{Direction, GameMode, GameResult, GameSettings, InboundMessage, OutboundMessage, PlayerNameInvalidReason},
+};
+use log::{debug, info, warn};
+use rand::{rngs::StdRng, FromEntropy, Rng, SeedableRng};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+const TRAINING_VENUE: &str = "training";
+const TOURNAMENT_VENUE: &str = "tournament";
+const TOURNAMENT_NAME: &str = "Local tournament";
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub training_bots: usize,
+    pub tournament_players: usize,
+    pub game_options: GameOptions,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig { training_bots: 2, tournament_players: 2, game_options: Default::default() }
+    }
+}
+
+// A stand-in for the snake server, running games with the offline engine
+pub struct Server {
+    socket: ws::WebSocket<ConnectionFactory>,
+}
+
+impl Server {
+    pub fn bind(address: &str, config: ServerConfig) -> ws::Result<Server> {
+        let rng = match config.game_options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let lobby = Lobby {
+            config,
+            rng,
+            noof_games: 0,
+            names: HashSet::new(),
+            waiting: Vec::new(),
+            tournament_game_id: None,
+            moves: HashMap::new(),
+        };
+        let factory = ConnectionFactory { lobby: Arc::new(Mutex::new(lobby)), noof_connections: 0 };
+        let socket = ws::WebSocket::new(factory)?.bind(address)?;
+        Ok(Server { socket })
+    }
+
+    pub fn local_addr(&self) -> ws::Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    // Can be used to shut the server down from another thread
+    pub fn broadcaster(&self) -> ws::Sender {
+        self.socket.broadcaster()
+    }
+
+    pub fn run(self) -> ws::Result<()> {
+        self.socket.run()?;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct Participant {
+    id: String,
+    name: String,
+    out: ws::Sender,
+}
+
+struct PlayerMove {
+    player_id: String,
+    game_tick: u32,
+    direction: Direction,
+}
+
+struct Lobby {
+    config: ServerConfig,
+    rng: StdRng,
+    noof_games: u32,
+    names: HashSet<String>,
+    waiting: Vec<Participant>,
+    tournament_game_id: Option<String>,
+    moves: HashMap<String, mpsc::Sender<PlayerMove>>,
+}
+
+impl Lobby {
+    fn next_game_id(&mut self) -> String {
+        self.noof_games += 1;
+        format!("game-{}", self.noof_games)
+    }
+
+    fn tournament_game_id(&mut self) -> String {
+        if self.tournament_game_id.is_none() {
+            self.tournament_game_id = Some(self.next_game_id());
+        }
+        self.tournament_game_id.clone().unwrap()
+    }
+
+    fn start_game(
+        &mut self,
+        lobby: Arc<Mutex<Lobby>>,
+        game_id: String,
+        settings: GameSettings,
+        participants: Vec<Participant>,
+        noof_bots: usize,
+        game_mode: GameMode,
+    ) {
+        let (sender, receiver) = mpsc::channel();
+        for participant in &participants {
+            self.moves.insert(participant.id.clone(), sender.clone());
+        }
+
+        let bots: Vec<(String, SafeBot)> =
+            (1..=noof_bots).map(|i| (format!("{}-bot-{}", game_id, i), SafeBot::new())).collect();
+        let players: Vec<(String, String)> = participants
+            .iter()
+            .map(|p| (p.id.clone(), p.name.clone()))
+            .chain(bots.iter().map(|(id, _)| (id.clone(), id.clone())))
+            .collect();
+
+        info!("Starting game {} with {} players", game_id, players.len());
+        let game = Game::new(game_id, settings, &self.config.game_options, &players, self.rng.gen());
+        thread::spawn(move || play_game(lobby, game, participants, bots, receiver, game_mode));
+    }
+
+    fn disconnect(&mut self, player_id: &str, name: &str) {
+        self.names.remove(name);
+        self.waiting.retain(|participant| participant.id != player_id);
+        self.moves.remove(player_id);
+    }
+}
+
+fn send(out: &ws::Sender, message: &InboundMessage) {
+    debug!("Sending message: {:?}", message);
+    match serde_json::to_string(message) {
+        Ok(json) => {
+            if let Err(error) = out.send(json) {
+                debug!("Failed to send message: {}", error);
+            }
+        }
+        Err(error) => warn!("Failed to serialize message: {}", error),
+    }
+}
+
+fn play_game(
+    lobby: Arc<Mutex<Lobby>>,
+    mut game: Game,
+    participants: Vec<Participant>,
+    mut bots: Vec<(String, SafeBot)>,
+    moves: mpsc::Receiver<PlayerMove>,
+    game_mode: GameMode,
+) {
+    let tick_duration = Duration::from_millis(game.settings().time_in_ms_per_tick.into());
+    let broadcast = |bots: &mut Vec<(String, SafeBot)>, message: &dyn Fn(&str) -> InboundMessage| {
+        for participant in &participants {
+            send(&participant.out, &message(&participant.id));
+        }
+        for (id, bot) in bots.iter_mut() {
+            notify(bot, &message(id));
+        }
+    };
+
+    broadcast(&mut bots, &|id| game.game_starting_message(id));
+
+    while !game.is_over() {
+        let mut registered = HashMap::new();
+        let mut pending = HashSet::new();
+        for participant in participants.iter().filter(|p| game.is_alive(&p.id)) {
+            send(&participant.out, &game.map_update_message(&participant.id));
+            pending.insert(participant.id.clone());
+        }
+        for (id, bot) in bots.iter_mut().filter(|(id, _)| game.is_alive(id)) {
+            notify(bot, &game.map_update_message(id));
+            registered.insert(id.clone(), bot.get_next_move(game.map(), id));
+        }
+
+        let deadline = Instant::now() + tick_duration;
+        while !pending.is_empty() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match moves.recv_timeout(timeout) {
+                Ok(player_move) => {
+                    if player_move.game_tick == game.game_tick() && pending.remove(&player_move.player_id) {
+                        registered.insert(player_move.player_id, player_move.direction);
+                    } else {
+                        debug!("Ignoring move from {} for tick {}", player_move.player_id, player_move.game_tick);
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        if !pending.is_empty() {
+            debug!("No move registered in time by {:?}", pending);
+        }
+
+        for death in game.tick(&registered) {
+            info!("Snake {} died: {:?}", death.player_id, death.reason);
+            broadcast(&mut bots, &|id| game.snake_dead_message(id, &death));
+        }
+    }
+
+    info!("Game {} ended after {} ticks", game.game_id(), game.game_tick());
+    broadcast(&mut bots, &|id| game.game_ended_message(id));
+    broadcast(&mut bots, &|id| game.game_result_message(id));
+
+    if game_mode == GameMode::Tournament {
+        let game_result: Vec<GameResult> = game
+            .player_ranks()
+            .into_iter()
+            .map(|rank| GameResult { name: rank.player_name, player_id: rank.player_id, points: rank.points })
+            .collect();
+        let player_winner_id = game_result.first().map(|result| result.player_id.clone()).unwrap_or_default();
+        broadcast(&mut bots, &|id| InboundMessage::TournamentEnded {
+            receiving_player_id: id.to_string(),
+            player_winner_id: player_winner_id.clone(),
+            game_id: game.game_id().to_string(),
+            game_result: game_result.clone(),
+            tournament_id: game.game_id().to_string(),
+            tournament_name: TOURNAMENT_NAME.to_string(),
+        });
+    }
+
+    let mut lobby = lobby.lock().unwrap();
+    for participant in &participants {
+        lobby.moves.remove(&participant.id);
+    }
+}
+
+struct ConnectionFactory {
+    lobby: Arc<Mutex<Lobby>>,
+    noof_connections: u32,
+}
+
+impl ws::Factory for ConnectionFactory {
+    type Handler = Connection;
+
+    fn connection_made(&mut self, out: ws::Sender) -> Connection {
+        self.noof_connections += 1;
+        Connection {
+            id: format!("player-{}", self.noof_connections),
+            out,
+            lobby: self.lobby.clone(),
+            game_mode: GameMode::Training,
+            registration: None,
+        }
+    }
+}
+
+struct Registration {
+    name: String,
+    game_id: String,
+    settings: GameSettings,
+    started: bool,
+}
+
+struct Connection {
+    id: String,
+    out: ws::Sender,
+    lobby: Arc<Mutex<Lobby>>,
+    game_mode: GameMode,
+    registration: Option<Registration>,
+}
+
+impl Connection {
+    fn register_player(&mut self, player_name: &str, game_settings: GameSettings) {
+        let mut lobby = self.lobby.lock().unwrap();
+
+        let reason = if player_name.is_empty() {
+            Some(PlayerNameInvalidReason::Empty)
+        } else if self.registration.is_some() || lobby.names.contains(player_name) {
+            Some(PlayerNameInvalidReason::Taken)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            send(&self.out, &InboundMessage::InvalidPlayerName { receiving_player_id: self.id.clone(), reason });
+            return;
+        }
+
+        let (game_id, settings) = if self.game_mode == GameMode::Training {
+            (lobby.next_game_id(), GameSettings { training_game: true, ..game_settings })
+        } else {
+            (lobby.tournament_game_id(), GameSettings::default())
+        };
+        lobby.names.insert(player_name.to_string());
+        info!("Registered player {} as {}", player_name, self.id);
+
+        send(
+            &self.out,
+            &InboundMessage::PlayerRegistered {
+                receiving_player_id: self.id.clone(),
+                name: player_name.to_string(),
+                game_id: game_id.clone(),
+                game_mode: self.game_mode,
+                game_settings: settings.clone(),
+            },
+        );
+
+        if self.game_mode == GameMode::Tournament {
+            lobby.waiting.push(Participant {
+                id: self.id.clone(),
+                name: player_name.to_string(),
+                out: self.out.clone(),
+            });
+            if lobby.waiting.len() >= lobby.config.tournament_players {
+                let participants = std::mem::take(&mut lobby.waiting);
+                lobby.tournament_game_id = None;
+                lobby.start_game(
+                    self.lobby.clone(),
+                    game_id.clone(),
+                    settings.clone(),
+                    participants,
+                    0,
+                    self.game_mode,
+                );
+            }
+        }
+        self.registration = Some(Registration { name: player_name.to_string(), game_id, settings, started: false });
+    }
+
+    fn start_game(&mut self) {
+        let registration = match self.registration.as_mut() {
+            Some(registration) if self.game_mode == GameMode::Training && !registration.started => registration,
+            _ => {
+                warn!("Ignoring StartGame from {}", self.id);
+                return;
+            }
+        };
+        registration.started = true;
+
+        let participant = Participant { id: self.id.clone(), name: registration.name.clone(), out: self.out.clone() };
+        let mut lobby = self.lobby.lock().unwrap();
+        let noof_bots = lobby.config.training_bots;
+        lobby.start_game(
+            self.lobby.clone(),
+            registration.game_id.clone(),
+            registration.settings.clone(),
+            vec![participant],
+            noof_bots,
+            self.game_mode,
+        );
+    }
+
+    fn register_move(&self, direction: Direction, game_tick: u32) {
+        let lobby = self.lobby.lock().unwrap();
+        if let Some(moves) = lobby.moves.get(&self.id) {
+            let _ = moves.send(PlayerMove { player_id: self.id.clone(), game_tick, direction });
+        }
+    }
+}
+
+impl ws::Handler for Connection {
+    fn on_open(&mut self, shake: ws::Handshake) -> ws::Result<()> {
+        let resource = shake.request.resource();
+        let venue = resource.trim_start_matches('/').split(['/', '?']).next().unwrap_or("");
+        self.game_mode = match venue {
+            TRAINING_VENUE => GameMode::Training,
+            TOURNAMENT_VENUE => GameMode::Tournament,
+            _ => {
+                warn!("Unknown venue requested: {}", resource);
+                return self.out.close_with_reason(ws::CloseCode::Policy, "Unknown venue");
+            }
+        };
+        info!("Player {} connected to {}", self.id, venue);
+        Ok(())
+    }
+
+    fn on_message(&mut self, message: ws::Message) -> ws::Result<()> {
+        let text = message.into_text()?;
+        let message = match serde_json::from_str::<OutboundMessage>(&text) {
+            Ok(message) => message,
+            Err(error) => {
+                warn!("Ignoring message that could not be parsed: {} ({})", text, error);
+                return Ok(());
+            }
+        };
+        debug!("Received message: {:?}", message);
+
+        match message {
+            OutboundMessage::ClientInfo { language, client_version, .. } => {
+                info!("Player {} uses {} client version {}", self.id, language, client_version);
+            }
+            OutboundMessage::RegisterPlayer { player_name, game_settings } => {
+                self.register_player(player_name, game_settings);
+            }
+            OutboundMessage::StartGame => self.start_game(),
+            OutboundMessage::RegisterMove { direction, game_tick, .. } => self.register_move(direction, game_tick),
+            OutboundMessage::HeartBeatRequest { .. } => {
+                send(&self.out, &InboundMessage::HeartBeatResponse { receiving_player_id: self.id.clone() });
+            }
+        }
+        Ok(())
+    }
+
+    fn on_close(&mut self, code: ws::CloseCode, reason: &str) {
+        debug!("Player {} disconnected with code {:?} and reason: {}", self.id, code, reason);
+        let name = self.registration.as_ref().map(|r| r.name.as_str()).unwrap_or("");
+        self.lobby.lock().unwrap().disconnect(&self.id, name);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        async_client::AsyncClient,
+        client::{Client, Config},
+        replay::{read_replay, replay_path, Origin},
+        types::{Map, PlayerRank},
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[derive(Clone, Default)]
+    struct RecordingPlayer {
+        messages: Rc<RefCell<Vec<InboundMessage>>>,
+        hooks: Rc<RefCell<Vec<&'static str>>>,
+    }
+
+    impl Player for RecordingPlayer {
+        fn get_next_move(&mut self, map: &Map, player_id: &str) -> Direction {
+            SafeBot::new().get_next_move(map, player_id)
+        }
+
+        fn on_message(&mut self, message: &InboundMessage) {
+            self.messages.borrow_mut().push(message.clone());
+        }
+
+        fn on_registered(&mut self, _: &str, _: GameMode) {
+            self.hooks.borrow_mut().push("registered");
+        }
+
+        fn on_game_starting(&mut self, _: &GameSettings) {
+            self.hooks.borrow_mut().push("game_starting");
+        }
+
+        fn on_game_ended(&mut self, _: &str, _: &Map) {
+            self.hooks.borrow_mut().push("game_ended");
+        }
+
+        fn on_game_result(&mut self, _: &[PlayerRank]) {
+            self.hooks.borrow_mut().push("game_result");
+        }
+
+        fn on_tournament_ended(&mut self, _: &str, _: &[GameResult]) {
+            self.hooks.borrow_mut().push("tournament_ended");
+        }
+    }
+
+    // Thinks for longer than a tick allows on the first move of every game
+    #[derive(Default)]
+    struct SlowPlayer {
+        moves: u32,
+    }
+
+    impl Player for SlowPlayer {
+        fn get_next_move(&mut self, map: &Map, player_id: &str) -> Direction {
+            self.moves += 1;
+            if self.moves == 1 {
+                thread::sleep(Duration::from_millis(300));
+            }
+            SafeBot::new().get_next_move(map, player_id)
+        }
+    }
+
+    fn start_server(config: ServerConfig) -> (SocketAddr, ws::Sender) {
+        let server = Server::bind("127.0.0.1:0", config).unwrap();
+        let address = server.local_addr().unwrap();
+        let broadcaster = server.broadcaster();
+        thread::spawn(move || server.run().unwrap());
+        (address, broadcaster)
+    }
+
+    fn get_server_config() -> ServerConfig {
+        ServerConfig {
+            training_bots: 1,
+            tournament_players: 2,
+            game_options: GameOptions { width: 15, height: 15, max_game_ticks: 50, seed: Some(11) },
+        }
+    }
+
+    fn get_client_config(address: SocketAddr, venue: &str, snake_name: &str) -> Config {
+        Config {
+            host: address.ip().to_string(),
+            port: i32::from(address.port()),
+            venue: venue.to_string(),
+            snake_name: snake_name.to_string(),
+            reconnect_attempts: 0,
+            reconnect_delay_ms: 0,
+            max_reconnect_delay_ms: 0,
+            ..Default::default()
+        }
+    }
+
+    fn play(config: Config) -> (Vec<InboundMessage>, Vec<&'static str>) {
+        let player = RecordingPlayer::default();
+        Client::connect(config, || player.clone()).unwrap();
+        let messages = player.messages.borrow().clone();
+        let hooks = player.hooks.borrow().clone();
+        (messages, hooks)
+    }
+
+    #[test]
+    fn client_plays_a_training_game() {
+        let (address, server) = start_server(get_server_config());
+        let (messages, hooks) = play(get_client_config(address, TRAINING_VENUE, "trainee"));
+
+        match messages.first() {
+            Some(InboundMessage::PlayerRegistered { game_mode, name, .. }) => {
+                assert_eq!(GameMode::Training, *game_mode);
+                assert_eq!("trainee", name);
+            }
+            other => panic!("Expected PlayerRegistered, got {:?}", other),
+        }
+        assert!(messages.iter().any(|m| matches!(m, InboundMessage::GameStarting { noof_players: 2, .. })));
+        assert!(messages.iter().any(|m| matches!(m, InboundMessage::MapUpdate { .. })));
+        assert!(messages.iter().any(|m| matches!(m, InboundMessage::GameEnded { .. })));
+        assert_eq!(vec!["registered", "game_starting", "game_ended"], hooks[..3].to_vec());
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn client_plays_training_games_back_to_back() {
+        let (address, server) = start_server(get_server_config());
+        let config = Config { games: Some(3), ..get_client_config(address, TRAINING_VENUE, "grinder") };
+        let outcomes = Client::connect(config, SafeBot::new).unwrap();
+
+        assert_eq!(3, outcomes.len());
+        let game_ids: HashSet<_> = outcomes.iter().map(|outcome| outcome.game_id.clone()).collect();
+        assert_eq!(3, game_ids.len());
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn fallback_move_is_sent_when_player_is_too_slow() {
+        let (address, server) = start_server(get_server_config());
+        let outcomes =
+            Client::connect(get_client_config(address, TRAINING_VENUE, "sloth"), SlowPlayer::default).unwrap();
+
+        assert_eq!(1, outcomes.len());
+        assert_eq!(1, outcomes[0].missed_deadlines);
+        assert!(outcomes[0].game_tick > 1);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn training_game_is_recorded() {
+        let directory = std::env::temp_dir().join("snakebot-recorded-training");
+        let _ = std::fs::remove_dir_all(&directory);
+        let (address, server) = start_server(get_server_config());
+        let config = Config {
+            replay_directory: Some(directory.to_str().unwrap().to_string()),
+            ..get_client_config(address, TRAINING_VENUE, "recorded")
+        };
+        let outcomes = Client::connect(config, SafeBot::new).unwrap();
+
+        let entries = read_replay(replay_path(&directory, &outcomes[0].game_id).unwrap()).unwrap();
+        let types: Vec<_> =
+            entries.iter().map(|entry| (entry.origin, entry.message["type"].as_str().unwrap())).collect();
+        assert_eq!((Origin::Client, "se.cygni.snake.api.request.ClientInfo"), types[0]);
+        assert!(types.contains(&(Origin::Server, "se.cygni.snake.api.event.MapUpdateEvent")));
+        assert!(types.contains(&(Origin::Client, "se.cygni.snake.api.request.RegisterMove")));
+        assert!(types.contains(&(Origin::Server, "se.cygni.snake.api.event.GameEndedEvent")));
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn clients_play_a_tournament() {
+        let (address, server) = start_server(get_server_config());
+        let players: Vec<_> = ["first", "second"]
+            .iter()
+            .map(|name| {
+                let config = get_client_config(address, TOURNAMENT_VENUE, name);
+                thread::spawn(move || play(config))
+            })
+            .collect();
+
+        for player in players {
+            let (messages, hooks) = player.join().unwrap();
+            assert_eq!(vec!["registered", "game_starting", "game_ended", "game_result", "tournament_ended"], hooks);
+            assert!(messages.iter().any(|m| matches!(m, InboundMessage::GameResult { .. })));
+            match messages.last() {
+                Some(InboundMessage::TournamentEnded { game_result, .. }) => assert_eq!(2, game_result.len()),
+                other => panic!("Expected TournamentEnded, got {:?}", other),
+            }
+        }
+        server.shutdown().unwrap();
+    }
+
+    #[tokio::test]
+    async fn async_clients_share_a_runtime() {
+        let (address, server) = start_server(get_server_config());
+        let first = AsyncClient::connect(get_client_config(address, TOURNAMENT_VENUE, "first"), SafeBot::new);
+        let second = AsyncClient::connect(get_client_config(address, TOURNAMENT_VENUE, "second"), SafeBot::new);
+        let (first, second) = tokio::join!(first, second);
+
+        assert_eq!(1, first.unwrap().len());
+        assert_eq!(1, second.unwrap().len());
+        server.shutdown().unwrap();
+    }
+
+    #[tokio::test]
+    async fn async_client_does_not_wait_for_a_slow_player() {
+        let (address, server) = start_server(get_server_config());
+        let outcomes = AsyncClient::connect(get_client_config(address, TRAINING_VENUE, "sloth"), SlowPlayer::default)
+            .await
+            .unwrap();
+
+        assert_eq!(1, outcomes[0].missed_deadlines);
+        assert!(outcomes[0].game_tick > 1);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn taken_player_name_is_rejected() {
+        let mut config = get_server_config();
+        config.tournament_players = 3;
+        let (address, server) = start_server(config);
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            ws::connect(format!("ws://{}/{}", address, TOURNAMENT_VENUE), |out| {
+                let sender = sender.clone();
+                let register = r#"{"type":"se.cygni.snake.api.request.RegisterPlayer","playerName":"twin","#;
+                let settings = serde_json::to_string(&GameSettings::default()).unwrap();
+                out.send(format!("{}\"gameSettings\":{}}}", register, settings)).unwrap();
+                out.send(format!("{}\"gameSettings\":{}}}", register, settings)).unwrap();
+                move |message: ws::Message| {
+                    sender.send(message.into_text()?).unwrap();
+                    Ok(())
+                }
+            })
+            .unwrap();
+        });
+
+        let registered = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        let rejected = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            serde_json::from_str::<InboundMessage>(&registered).unwrap(),
+            InboundMessage::PlayerRegistered { .. }
+        ));
+        assert!(matches!(
+            serde_json::from_str::<InboundMessage>(&rejected).unwrap(),
+            InboundMessage::InvalidPlayerName { reason: PlayerNameInvalidReason::Taken, .. }
+        ));
+        server.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+use crate::{
+    types::{DeathReason, Direction, GameSettings, Map, Position, SnakeInfo},
+    utils::{Coordinate, DIRECTIONS},
+};
+use log::debug;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnakeDeath {
+    pub player_id: String,
+    pub coordinate: Coordinate,
+    pub reason: DeathReason,
+}
+
+// The direction from the body part behind the head to the head, or Down while the snake is still coiled up
+pub fn current_direction(map: &Map, snake: &SnakeInfo) -> Direction {
+    match snake.positions.get(0..2) {
+        Some(&[head, neck]) if head - neck == -map.width => Direction::Up,
+        Some(&[head, neck]) if head - neck == -1 => Direction::Left,
+        Some(&[head, neck]) if head - neck == 1 => Direction::Right,
+        _ => Direction::Down,
+    }
+}
+
+// Every direction but back into the body
+pub fn candidate_moves(map: &Map, snake: &SnakeInfo) -> Vec<Direction> {
+    let neck = snake.positions.get(1).filter(|&&neck| neck != snake.positions[0]);
+    let head = Coordinate::from_position(snake.positions[0], map.width);
+    DIRECTIONS
+        .iter()
+        .cloned()
+        .filter(|direction| neck != Some(&(head + direction.to_movement_delta()).to_position(map.width)))
+        .collect()
+}
+
+// Straight ahead if possible, otherwise the first free direction
+pub fn default_move(map: &Map, snake: &SnakeInfo) -> Direction {
+    let current = current_direction(map, snake);
+    Some(current)
+        .into_iter()
+        .chain(DIRECTIONS.iter().cloned())
+        .find(|&direction| map.can_snake_move_in_direction(snake, direction))
+        .unwrap_or(current)
+}
+
+// For searches asked for a move without a deadline from the client, half a tick from now
+pub fn default_deadline(settings: &GameSettings) -> Instant {
+    Instant::now() + Duration::from_millis(u64::from(settings.time_in_ms_per_tick) / 2)
+}
+
+// The map after one world tick, see apply_moves
+pub fn next_map(map: &Map, settings: &GameSettings, moves: &HashMap<String, Direction>) -> (Map, Vec<SnakeDeath>) {
+    let mut next = map.clone();
+    let deaths = apply_moves(&mut next, settings, moves);
+    (next, deaths)
+}
+
+// Advances the map one world tick. Snakes without a move keep going in their current direction, and no new food
+// is placed since the server does that at random.
+pub fn apply_moves(map: &mut Map, settings: &GameSettings, moves: &HashMap<String, Direction>) -> Vec<SnakeDeath> {
+    map.world_tick += 1;
+
+    let width = map.width;
+    let south_east = Coordinate(width - 1, map.height - 1);
+    let n = settings.spontaneous_growth_every_n_world_tick;
+    let spontaneous_growth = n > 0 && map.world_tick.is_multiple_of(n);
+
+    let mut heads: Vec<Option<Coordinate>> = vec![None; map.snake_infos.len()];
+    let mut previous_heads: Vec<Option<Coordinate>> = vec![None; map.snake_infos.len()];
+    let mut removed_tails: Vec<Option<Position>> = vec![None; map.snake_infos.len()];
+    let mut deaths: Vec<(usize, Coordinate, DeathReason)> = Vec::new();
+
+    for index in 0..map.snake_infos.len() {
+        let snake = &map.snake_infos[index];
+        if snake.positions.is_empty() {
+            continue;
+        }
+        let direction = moves.get(&snake.id).cloned().unwrap_or_else(|| current_direction(map, snake));
+
+        let current_head = Coordinate::from_position(snake.positions[0], width);
+        previous_heads[index] = Some(current_head);
+        let head = current_head + direction.to_movement_delta();
+        if !head.is_within_square(Coordinate(0, 0), south_east) {
+            deaths.push((index, current_head, DeathReason::CollisionWithWall));
+            continue;
+        }
+
+        let position = head.to_position(width);
+        let grows = spontaneous_growth || map.food_positions.contains(&position);
+        let snake = &mut map.snake_infos[index];
+        snake.positions.insert(0, position);
+        if !grows {
+            removed_tails[index] = snake.positions.pop();
+        }
+        heads[index] = Some(head);
+    }
+
+    let mut nibbles: Vec<(usize, usize)> = Vec::new();
+    let mut caused_deaths: Vec<usize> = Vec::new();
+    for (index, head) in heads.iter().enumerate() {
+        let head = match head {
+            Some(head) => *head,
+            None => continue,
+        };
+        let position = head.to_position(width);
+
+        if map.obstacle_positions.contains(&position) {
+            deaths.push((index, head, DeathReason::CollisionWithObstacle));
+        } else if heads.iter().enumerate().any(|(other, h)| {
+            // Snakes that swap places pass through each other's heads, which is as much a head-on collision
+            other != index && (*h == Some(head) || (*h == previous_heads[index] && previous_heads[other] == Some(head)))
+        }) {
+            deaths.push((index, head, DeathReason::CollisionWithSnake));
+        } else if map.snake_infos[index].positions[1..].contains(&position) {
+            deaths.push((index, head, DeathReason::CollisionWithSelf));
+        } else if let Some(victim) = snake_body_at(map, position, &heads, index) {
+            let victim_snake = &map.snake_infos[victim];
+            let is_tail = victim_snake.positions.len() > 1 && victim_snake.positions.last() == Some(&position);
+            if is_tail && settings.head_to_tail_consumes && victim_snake.tail_protected_for_game_ticks == 0 {
+                nibbles.push((index, victim));
+            } else {
+                deaths.push((index, head, DeathReason::CollisionWithSnake));
+                caused_deaths.push(victim);
+            }
+        }
+    }
+
+    for snake in map.snake_infos.iter_mut() {
+        snake.tail_protected_for_game_ticks = snake.tail_protected_for_game_ticks.saturating_sub(1);
+    }
+
+    for (attacker, victim) in nibbles {
+        debug!("Snake {} nibbled the tail of {}", attacker, victim);
+        map.snake_infos[victim].positions.pop();
+        let snake = &mut map.snake_infos[attacker];
+        snake.points += settings.points_per_nibble as i32;
+        snake.tail_protected_for_game_ticks = settings.noof_rounds_tail_protected_after_nibble;
+        if settings.tail_consume_grows {
+            if let Some(tail) = removed_tails[attacker].take() {
+                snake.positions.push(tail);
+            }
+        }
+    }
+
+    for victim in caused_deaths {
+        map.snake_infos[victim].points += settings.points_per_caused_death as i32;
+    }
+
+    let mut dead = vec![false; map.snake_infos.len()];
+    for &(index, _, _) in &deaths {
+        dead[index] = true;
+    }
+
+    for (index, snake) in map.snake_infos.iter_mut().enumerate() {
+        if dead[index] {
+            snake.positions.clear();
+        } else if let Some(head) = heads[index] {
+            let position = head.to_position(width);
+            if let Some(food) = map.food_positions.iter().position(|&food| food == position) {
+                map.food_positions.remove(food);
+                snake.points += settings.points_per_food as i32;
+            }
+            if removed_tails[index].is_none() {
+                snake.points += settings.points_per_length as i32;
+            }
+        }
+    }
+
+    deaths
+        .into_iter()
+        .map(|(index, coordinate, reason)| SnakeDeath {
+            player_id: map.snake_infos[index].id.clone(),
+            coordinate,
+            reason,
+        })
+        .collect()
+}
+
+// The snake other than `ignored` with a body part at `position`, the heads that just moved do not count
+fn snake_body_at(map: &Map, position: Position, heads: &[Option<Coordinate>], ignored: usize) -> Option<usize> {
+    map.snake_infos.iter().enumerate().position(|(index, snake)| {
+        let body = if heads[index].is_some() { &snake.positions[1..] } else { &snake.positions[..] };
+        index != ignored && body.contains(&position)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test::get_map;
+
+    const MAP_WIDTH: i32 = 5;
+
+    fn get_settings() -> GameSettings {
+        GameSettings { spontaneous_growth_every_n_world_tick: 0, ..Default::default() }
+    }
+
+    fn moves(directions: &[(&str, Direction)]) -> HashMap<String, Direction> {
+        directions.iter().map(|&(id, direction)| (id.to_string(), direction)).collect()
+    }
+
+    fn positions(coordinates: &[Coordinate]) -> Vec<Position> {
+        coordinates.iter().map(|c| c.to_position(MAP_WIDTH)).collect()
+    }
+
+    #[test]
+    fn next_map_leaves_the_current_map_alone() {
+        let map = get_map(MAP_WIDTH, MAP_WIDTH, &[("0", &[Coordinate(2, 2), Coordinate(2, 3)])], &[], &[]);
+        let (next, deaths) = next_map(&map, &get_settings(), &moves(&[("0", Direction::Left)]));
+
+        assert!(deaths.is_empty());
+        assert_eq!(1, next.world_tick);
+        assert_eq!(positions(&[Coordinate(1, 2), Coordinate(2, 2)]), next.snake_infos[0].positions);
+        assert_eq!(positions(&[Coordinate(2, 2), Coordinate(2, 3)]), map.snake_infos[0].positions);
+    }
+
+    #[test]
+    fn snakes_without_a_move_keep_their_direction() {
+        let map = get_map(
+            MAP_WIDTH,
+            MAP_WIDTH,
+            &[("0", &[Coordinate(2, 2), Coordinate(1, 2)]), ("1", &[Coordinate(0, 0)])],
+            &[],
+            &[],
+        );
+        let (next, _) = next_map(&map, &get_settings(), &HashMap::new());
+
+        assert_eq!(positions(&[Coordinate(3, 2), Coordinate(2, 2)]), next.snake_infos[0].positions);
+        assert_eq!(positions(&[Coordinate(0, 1)]), next.snake_infos[1].positions);
+    }
+
+    #[test]
+    fn snakes_grow_spontaneously() {
+        let mut map = get_map(MAP_WIDTH, MAP_WIDTH, &[("0", &[Coordinate(2, 2)])], &[], &[]);
+        map.world_tick = 2;
+        let settings = GameSettings { spontaneous_growth_every_n_world_tick: 3, ..Default::default() };
+        let (next, _) = next_map(&map, &settings, &moves(&[("0", Direction::Up)]));
+
+        assert_eq!(positions(&[Coordinate(2, 1), Coordinate(2, 2)]), next.snake_infos[0].positions);
+    }
+
+    #[test]
+    fn snake_can_follow_its_own_tail() {
+        let map = get_map(
+            MAP_WIDTH,
+            MAP_WIDTH,
+            &[("0", &[Coordinate(1, 1), Coordinate(2, 1), Coordinate(2, 2), Coordinate(1, 2)])],
+            &[],
+            &[],
+        );
+        let (next, deaths) = next_map(&map, &get_settings(), &moves(&[("0", Direction::Down)]));
+
+        assert!(deaths.is_empty());
+        assert_eq!(Coordinate(1, 2).to_position(MAP_WIDTH), next.snake_infos[0].positions[0]);
+    }
+
+    #[test]
+    fn protected_tails_can_not_be_nibbled() {
+        let mut map = get_map(
+            MAP_WIDTH,
+            MAP_WIDTH,
+            &[
+                ("0", &[Coordinate(1, 3), Coordinate(0, 3)]),
+                ("1", &[Coordinate(2, 0), Coordinate(2, 1), Coordinate(2, 2), Coordinate(2, 3), Coordinate(2, 4)]),
+            ],
+            &[],
+            &[],
+        );
+        map.snake_infos[1].tail_protected_for_game_ticks = 2;
+        let (next, deaths) =
+            next_map(&map, &get_settings(), &moves(&[("0", Direction::Right), ("1", Direction::Right)]));
+
+        assert_eq!(
+            vec![SnakeDeath {
+                player_id: "0".to_string(),
+                coordinate: Coordinate(2, 3),
+                reason: DeathReason::CollisionWithSnake
+            }],
+            deaths
+        );
+        assert!(next.snake_infos[0].positions.is_empty());
+        assert_eq!(1, next.snake_infos[1].tail_protected_for_game_ticks);
+    }
+
+    #[test]
+    fn snakes_swapping_places_collide_head_on() {
+        let map = get_map(MAP_WIDTH, MAP_WIDTH, &[("0", &[Coordinate(1, 1)]), ("1", &[Coordinate(2, 1)])], &[], &[]);
+        let (next, deaths) =
+            next_map(&map, &get_settings(), &moves(&[("0", Direction::Right), ("1", Direction::Left)]));
+
+        assert_eq!(
+            vec![
+                (Coordinate(2, 1), DeathReason::CollisionWithSnake),
+                (Coordinate(1, 1), DeathReason::CollisionWithSnake)
+            ],
+            deaths.iter().map(|death| (death.coordinate, death.reason)).collect::<Vec<_>>()
+        );
+        assert!(next.snake_infos.iter().all(|snake| snake.positions.is_empty()));
+    }
+}
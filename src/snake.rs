@@ -1,11 +1,11 @@
-use crate::{
+use log::debug;
+use snakebot_rust::{
     client::Player,
-    types::{Direction, InboundMessage, Map},
-    utils::Coordinate,
+    types::{Direction, GameSettings, Map},
+    utils::DIRECTIONS,
 };
-use log::debug;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Snake;
 
 impl Snake {
@@ -14,27 +14,24 @@ impl Snake {
     }
 }
 
-const DIRECTIONS: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
-
 impl Player for Snake {
     fn get_next_move(&mut self, map: &Map, player_id: &str) -> Direction {
-        let snake_info = map.get_snake_by_id(&player_id).unwrap();
-
-        debug!(
-            "Food can be found at {:?}",
-            map.food_positions.iter().map(|pos| Coordinate::from_position(*pos, map.width)).collect::<Vec<_>>()
-        );
-
-        debug!(
-            "My snake positions are {:?}",
-            snake_info.positions.iter().map(|pos| Coordinate::from_position(*pos, map.width)).collect::<Vec<_>>()
-        );
-
-        for &dir in DIRECTIONS.iter() {
-            if map.can_snake_move_in_direction(snake_info, dir) {
-                debug!("Snake will move in direction {:?}", dir);
-                return dir;
-            }
+        let snake_info = map.get_snake_by_id(player_id).unwrap();
+
+        debug!("Map at tick {}:\n{}", map.world_tick, map);
+
+        // Avoid pockets too small for the body unless the tail can be followed out of them,
+        // then prefer the direction with the most room
+        let length = snake_info.positions.len();
+        let best = DIRECTIONS
+            .iter()
+            .filter_map(|&dir| map.reachable_area_in_direction(snake_info, dir).map(|area| (dir, area)))
+            .inspect(|(dir, area)| debug!("Moving {:?} can reach {:?}", dir, area))
+            .max_by_key(|(_, area)| (area.tail_reachable || area.tiles >= length, area.tiles));
+
+        if let Some((dir, _)) = best {
+            debug!("Snake will move in direction {:?}", dir);
+            return dir;
         }
 
         debug!("Snake cannot but will move down.");
@@ -42,9 +39,7 @@ impl Player for Snake {
         Direction::Down
     }
 
-    fn on_message(&mut self, message: &InboundMessage) {
-        if let InboundMessage::GameStarting { .. } = message {
-            // Reset snake state here
-        }
+    fn on_game_starting(&mut self, _: &GameSettings) {
+        // Reset snake state here
     }
 }
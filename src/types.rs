@@ -1,8 +1,14 @@
+use serde::{
+    de::{self, value::MapDeserializer},
+    Deserialize as _,
+};
 use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fmt, iter};
 
 pub type Position = i32;
 
-#[derive(Debug, PartialEq, Copy, Clone, Serialize)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum Direction {
     Up,
@@ -11,7 +17,8 @@ pub enum Direction {
     Right,
 }
 
-#[derive(Debug, PartialEq, Deserialize)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum DeathReason {
     CollisionWithWall,
     CollisionWithObstacle,
@@ -19,7 +26,7 @@ pub enum DeathReason {
     CollisionWithSelf,
 }
 
-#[derive(Debug, PartialEq, Deserialize)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum GameMode {
     Training,
@@ -28,24 +35,26 @@ pub enum GameMode {
     Arena,
 }
 
-#[derive(Debug, PartialEq, Deserialize)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum PlayerNameInvalidReason {
     Taken,
     Empty,
     InvalidCharacter,
 }
 
-#[derive(Debug, PartialEq, Deserialize)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SnakeInfo {
     pub id: String,
     pub name: String,
+    #[serde(default)]
     pub points: i32,
     pub positions: Vec<Position>,
+    #[serde(default)]
     pub tail_protected_for_game_ticks: u32,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GameResult {
     pub name: String,
@@ -53,7 +62,7 @@ pub struct GameResult {
     pub points: i32,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PlayerRank {
     pub player_name: String,
@@ -63,19 +72,23 @@ pub struct PlayerRank {
     pub alive: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Map {
     pub width: i32,
     pub height: i32,
+    #[serde(default)]
     pub world_tick: u32,
     pub snake_infos: Vec<SnakeInfo>,
+    #[serde(default)]
     pub food_positions: Vec<Position>,
+    #[serde(default)]
     pub obstacle_positions: Vec<Position>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+// Settings the server leaves out keep their defaults
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
 pub struct GameSettings {
     pub max_noof_players: u32,
     pub start_snake_length: u32,
@@ -118,13 +131,14 @@ impl Default for GameSettings {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum InboundMessage {
     #[serde(rename = "se.cygni.snake.api.event.GameEndedEvent", rename_all = "camelCase")]
     GameEnded {
         receiving_player_id: String,
         player_winner_id: String,
+        #[serde(default)]
         player_winner_name: String,
         game_id: String,
         game_tick: u32,
@@ -141,9 +155,13 @@ pub enum InboundMessage {
     GameStarting {
         receiving_player_id: String,
         game_id: String,
+        #[serde(default)]
         noof_players: u32,
+        #[serde(default)]
         width: u32,
+        #[serde(default)]
         height: u32,
+        #[serde(default)]
         game_settings: GameSettings,
     },
 
@@ -166,6 +184,7 @@ pub enum InboundMessage {
         name: String,
         game_id: String,
         game_mode: GameMode,
+        #[serde(default)]
         game_settings: GameSettings,
     },
 
@@ -186,12 +205,68 @@ pub enum InboundMessage {
         player_winner_id: String,
         game_id: String,
         game_result: Vec<GameResult>,
+        #[serde(default)]
         tournament_id: String,
+        #[serde(default)]
         tournament_name: String,
     },
+
+    // A message type this client does not know, like an event added in a newer server. Decode only: it is made by
+    // `from_value` and serializing it is an error, send `raw` to pass it on.
+    #[serde(skip)]
+    Unknown { type_name: String, raw: Value },
+}
+
+// The error of decoding nothing but the type of a message, which tells unknown types apart from missing fields
+#[derive(Debug)]
+enum TypeError {
+    Unknown,
+    Other,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+impl de::Error for TypeError {
+    fn custom<T: fmt::Display>(_: T) -> TypeError {
+        TypeError::Other
+    }
+
+    fn unknown_variant(_: &str, _: &'static [&'static str]) -> TypeError {
+        TypeError::Unknown
+    }
+}
+
+fn is_inbound_type(type_name: &str) -> bool {
+    let message = MapDeserializer::<_, TypeError>::new(iter::once(("type", type_name)));
+    !matches!(InboundMessage::deserialize(message), Err(TypeError::Unknown))
+}
+
+impl InboundMessage {
+    pub fn from_json(text: &str) -> serde_json::Result<InboundMessage> {
+        InboundMessage::from_value(serde_json::from_str(text)?)
+    }
+
+    // Messages of an unknown type become Unknown, but known messages that can not be read are still an error
+    pub fn from_value(raw: Value) -> serde_json::Result<InboundMessage> {
+        match InboundMessage::deserialize(&raw) {
+            Ok(message) => Ok(message),
+            Err(error) => match raw.get("type").and_then(Value::as_str) {
+                Some(type_name) if !is_inbound_type(type_name) => {
+                    Ok(InboundMessage::Unknown { type_name: type_name.to_string(), raw: raw.clone() })
+                }
+                _ => Err(error),
+            },
+        }
+    }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum OutboundMessage<'a> {
     #[serde(rename = "se.cygni.snake.api.request.ClientInfo", rename_all = "camelCase")]
@@ -215,3 +290,66 @@ pub enum OutboundMessage<'a> {
     #[serde(rename = "se.cygni.snake.api.request.StartGame", rename_all = "camelCase")]
     StartGame,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn unknown_message_types_are_kept_raw() {
+        let raw = json!({"type": "se.cygni.snake.api.event.ArenaUpdateEvent", "arenaName": "arena"});
+
+        match InboundMessage::from_value(raw.clone()).unwrap() {
+            InboundMessage::Unknown { type_name, raw: kept } => {
+                assert_eq!("se.cygni.snake.api.event.ArenaUpdateEvent", type_name);
+                assert_eq!(raw, kept);
+            }
+            message => panic!("Expected an unknown message, got {:?}", message),
+        }
+    }
+
+    #[test]
+    fn unknown_messages_are_decode_only() {
+        let raw = json!({"type": "se.cygni.snake.api.event.ArenaUpdateEvent"});
+        let message = InboundMessage::from_value(raw.clone()).unwrap();
+
+        assert!(serde_json::to_value(&message).is_err());
+        match message {
+            InboundMessage::Unknown { raw: kept, .. } => assert_eq!(raw, kept),
+            message => panic!("Expected an unknown message, got {:?}", message),
+        }
+    }
+
+    #[test]
+    fn known_types_come_from_the_enum() {
+        assert!(is_inbound_type("se.cygni.snake.api.event.MapUpdateEvent"));
+        assert!(is_inbound_type("se.cygni.snake.api.exception.InvalidPlayerName"));
+        assert!(!is_inbound_type("se.cygni.snake.api.event.ArenaUpdateEvent"));
+        assert!(!is_inbound_type("Unknown"));
+    }
+
+    #[test]
+    fn missing_optional_and_extra_fields_are_tolerated() {
+        let text = r#"{"type": "se.cygni.snake.api.event.GameStartingEvent", "receivingPlayerId": "me",
+                       "gameId": "game", "gameSettings": {"timeInMsPerTick": 100, "newSetting": true},
+                       "spectators": 3}"#;
+
+        match InboundMessage::from_json(text).unwrap() {
+            InboundMessage::GameStarting { game_settings, noof_players, .. } => {
+                assert_eq!(100, game_settings.time_in_ms_per_tick);
+                assert_eq!(GameSettings::default().max_noof_players, game_settings.max_noof_players);
+                assert_eq!(0, noof_players);
+            }
+            message => panic!("Expected GameStarting, got {:?}", message),
+        }
+    }
+
+    #[test]
+    fn known_messages_with_invalid_fields_are_an_error() {
+        let text = r#"{"type": "se.cygni.snake.api.event.MapUpdateEvent", "gameTick": "soon"}"#;
+
+        assert!(InboundMessage::from_json(text).is_err());
+        assert!(InboundMessage::from_json(r#"{"gameTick": 1}"#).is_err());
+    }
+}
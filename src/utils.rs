@@ -1,8 +1,10 @@
 use crate::types::{Direction, Map, Position, SnakeInfo};
 use std::ops::Add;
 
+pub const DIRECTIONS: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub struct Coordinate(i32, i32);
+pub struct Coordinate(pub i32, pub i32);
 
 impl Coordinate {
     pub fn from_position(position: Position, map_width: i32) -> Coordinate {
@@ -16,7 +18,6 @@ impl Coordinate {
         x + y * map_width
     }
 
-    #[allow(dead_code)]
     pub fn manhattan_distance_to(self, goal: Coordinate) -> i32 {
         let Coordinate(x0, y0) = self;
         let Coordinate(x1, y1) = goal;
@@ -79,10 +80,12 @@ impl Map {
         self.snake_infos.iter().find(|s| s.id == id)
     }
 
-    pub fn get_tile_at(&self, coordinate: Coordinate) -> Tile {
+    pub fn get_tile_at(&self, coordinate: Coordinate) -> Tile<'_> {
         let position = coordinate.to_position(self.width);
 
-        if self.obstacle_positions.contains(&position) {
+        if !self.inside_map(coordinate) {
+            Tile::Wall
+        } else if self.obstacle_positions.contains(&position) {
             Tile::Obstacle { coordinate }
         } else if self.food_positions.contains(&position) {
             Tile::Food { coordinate }
@@ -92,19 +95,13 @@ impl Map {
             } else {
                 Tile::SnakeBody { coordinate, snake }
             }
-        } else if !self.inside_map(coordinate) {
-            Tile::Wall
         } else {
             Tile::Empty { coordinate }
         }
     }
 
     pub fn is_tile_available_for_movement(&self, coordinate: Coordinate) -> bool {
-        match self.get_tile_at(coordinate) {
-            Tile::Empty { .. } => true,
-            Tile::Food { .. } => true,
-            _ => false,
-        }
+        matches!(self.get_tile_at(coordinate), Tile::Empty { .. } | Tile::Food { .. })
     }
 
     pub fn can_snake_move_in_direction(&self, snake: &SnakeInfo, direction: Direction) -> bool {
@@ -122,8 +119,11 @@ impl Map {
 }
 
 #[cfg(test)]
-mod test {
+// These tests predate the clippy gate and are kept as they were written
+#[allow(clippy::bool_assert_comparison, clippy::needless_borrow, clippy::useless_vec)]
+pub(crate) mod test {
     use super::*;
+    use std::time::{Duration, Instant};
 
     const MAP_WIDTH: i32 = 3;
 
@@ -163,6 +163,94 @@ mod test {
         }
     }
 
+    // A map for the tests of other modules. Each snake is its id and its coordinates from the head, and is named after
+    // its id.
+    pub(crate) fn get_map(
+        width: i32,
+        height: i32,
+        snakes: &[(&str, &[Coordinate])],
+        food: &[Coordinate],
+        obstacles: &[Coordinate],
+    ) -> Map {
+        let to_positions = |coordinates: &[Coordinate]| coordinates.iter().map(|c| c.to_position(width)).collect();
+        Map {
+            width,
+            height,
+            world_tick: 0,
+            snake_infos: snakes
+                .iter()
+                .map(|&(id, coordinates)| SnakeInfo {
+                    name: id.to_string(),
+                    points: 0,
+                    tail_protected_for_game_ticks: 0,
+                    positions: to_positions(coordinates),
+                    id: id.to_string(),
+                })
+                .collect(),
+            food_positions: to_positions(food),
+            obstacle_positions: to_positions(obstacles),
+        }
+    }
+
+    // A 5x5 map where snake 1, with its head at (0, 0), wraps around food in a pocket. `others` are added after it.
+    //yx01234
+    //0 1 F
+    //1 1 #
+    //2 111#
+    //3    #
+    //4
+    pub(crate) fn get_pocket_map(others: &[(&str, &[Coordinate])]) -> Map {
+        let mut snakes: Vec<(&str, &[Coordinate])> =
+            vec![("1", &[Coordinate(0, 0), Coordinate(0, 1), Coordinate(0, 2), Coordinate(1, 2), Coordinate(2, 2)])];
+        snakes.extend_from_slice(others);
+        get_map(5, 5, &snakes, &[Coordinate(2, 0)], &[Coordinate(2, 1), Coordinate(3, 2), Coordinate(3, 3)])
+    }
+
+    // Going up leads into a pocket of two tiles, far too small for the snake "me", so only going down keeps it alive
+    // for more than a couple of ticks
+    //yx012345
+    //0  # #
+    //1  # #
+    //2 111###
+    //3 1
+    //4 1    2
+    //5 1
+    pub(crate) fn get_dead_end_map() -> Map {
+        get_map(
+            6,
+            6,
+            &[
+                (
+                    "me",
+                    &[
+                        Coordinate(2, 2),
+                        Coordinate(1, 2),
+                        Coordinate(0, 2),
+                        Coordinate(0, 3),
+                        Coordinate(0, 4),
+                        Coordinate(0, 5),
+                    ],
+                ),
+                ("other", &[Coordinate(5, 4)]),
+            ],
+            &[],
+            &[
+                Coordinate(1, 0),
+                Coordinate(1, 1),
+                Coordinate(3, 0),
+                Coordinate(3, 1),
+                Coordinate(3, 2),
+                Coordinate(4, 2),
+                Coordinate(5, 2),
+            ],
+        )
+    }
+
+    // A deadline no search in the tests gets near
+    pub(crate) fn far_future() -> Instant {
+        Instant::now() + Duration::from_secs(60)
+    }
+
     #[test]
     fn snake_can_be_found_by_id() {
         let map = get_test_map();
@@ -233,4 +321,13 @@ mod test {
 
         assert_eq!(false, map.can_snake_move_in_direction(&snake, Direction::Down));
     }
+
+    #[test]
+    fn tiles_outside_the_map_are_walls() {
+        let map = get_test_map();
+
+        // Both wrap around to positions inside the map
+        assert_eq!(Tile::Wall, map.get_tile_at(Coordinate(3, 0)));
+        assert_eq!(Tile::Wall, map.get_tile_at(Coordinate(-1, 2)));
+    }
 }
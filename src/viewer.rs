@@ -0,0 +1,339 @@
+use crate::{
+    client::Player,
+    engine::Death,
+    render::snake_letter,
+    replay::{inbound_messages, ReplayEntry},
+    types::{DeathReason, Direction, GameMode, GameResult, GameSettings, InboundMessage, Map, PlayerRank},
+    utils::Coordinate,
+};
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::Print,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use log::warn;
+use std::{
+    cmp::Reverse,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+// The game as it looked after one tick
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub game_tick: u32,
+    pub map: Map,
+    // Every death so far, in the order they happened
+    pub deaths: Vec<Death>,
+    // Set on the last frame of a game
+    pub player_winner_id: Option<String>,
+}
+
+impl Frame {
+    fn name_of<'a>(&'a self, player_id: &'a str) -> &'a str {
+        self.map.get_snake_by_id(player_id).map_or(player_id, |snake| snake.name.as_str())
+    }
+}
+
+// The frames of one game, built from the messages the server sends
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    pub frames: Vec<Frame>,
+    deaths: Vec<Death>,
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Default::default()
+    }
+
+    pub fn from_replay(entries: &[ReplayEntry]) -> Timeline {
+        let mut timeline = Timeline::new();
+        for message in inbound_messages(entries) {
+            timeline.push(&message);
+        }
+        timeline
+    }
+
+    // Returns true if the message added a frame
+    pub fn push(&mut self, message: &InboundMessage) -> bool {
+        match message {
+            InboundMessage::GameStarting { .. } => {
+                self.frames.clear();
+                self.deaths.clear();
+                false
+            }
+            InboundMessage::SnakeDead { game_tick, player_id, x, y, death_reason, .. } => {
+                self.deaths.push(Death {
+                    player_id: player_id.clone(),
+                    game_tick: *game_tick,
+                    coordinate: Coordinate(*x as i32, *y as i32),
+                    reason: *death_reason,
+                });
+                false
+            }
+            InboundMessage::MapUpdate { game_tick, map, .. } => self.add_frame(*game_tick, map, None),
+            InboundMessage::GameEnded { game_tick, map, player_winner_id, .. } => {
+                self.add_frame(*game_tick, map, Some(player_winner_id.clone()))
+            }
+            _ => false,
+        }
+    }
+
+    fn add_frame(&mut self, game_tick: u32, map: &Map, player_winner_id: Option<String>) -> bool {
+        let deaths = self.deaths.clone();
+        self.frames.push(Frame { game_tick, map: map.clone(), deaths, player_winner_id });
+        true
+    }
+}
+
+// The lines shown for a frame: the tick, the map, the scoreboard and the deaths
+pub fn draw(frame: &Frame, colors: bool) -> Vec<String> {
+    let mut lines = vec![match frame.player_winner_id {
+        Some(ref winner) => format!("Tick {}, won by {}", frame.game_tick, frame.name_of(winner)),
+        None => format!("Tick {}", frame.game_tick),
+    }];
+    lines.extend(frame.map.render().colors(colors).to_string().lines().map(str::to_string));
+
+    lines.push(String::new());
+    let mut scoreboard: Vec<_> = frame.map.snake_infos.iter().enumerate().collect();
+    scoreboard.sort_by_key(|(_, snake)| Reverse(snake.points));
+    for (owner, snake) in scoreboard {
+        let status = if snake.positions.is_empty() {
+            "dead".to_string()
+        } else if snake.tail_protected_for_game_ticks > 0 {
+            format!("tail protected for {} ticks", snake.tail_protected_for_game_ticks)
+        } else {
+            String::new()
+        };
+        let line = format!(
+            "{} {:<16} {:>4} points {:>3} long  {}",
+            snake_letter(owner),
+            snake.name,
+            snake.points,
+            snake.positions.len(),
+            status
+        );
+        lines.push(line.trim_end().to_string());
+    }
+
+    if !frame.deaths.is_empty() {
+        lines.push(String::new());
+    }
+    for death in &frame.deaths {
+        lines.push(format!(
+            "Tick {}: {} died at {:?}, {:?}",
+            death.game_tick,
+            frame.name_of(&death.player_id),
+            death.coordinate,
+            death.reason
+        ));
+    }
+    lines
+}
+
+fn show<W: Write>(out: &mut W, lines: &[String]) -> io::Result<()> {
+    queue!(out, Clear(ClearType::All))?;
+    for (row, line) in lines.iter().enumerate() {
+        queue!(out, MoveTo(0, row as u16), Print(line))?;
+    }
+    out.flush()
+}
+
+// Steps through the frames with the keyboard until the user quits
+pub fn browse(timeline: &Timeline) -> io::Result<()> {
+    let mut out = io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(out, EnterAlternateScreen, Hide)?;
+    let result = browse_frames(&mut out, &timeline.frames);
+    execute!(out, Show, LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    result
+}
+
+fn browse_frames<W: Write>(out: &mut W, frames: &[Frame]) -> io::Result<()> {
+    if frames.is_empty() {
+        return Ok(());
+    }
+    let last = frames.len() - 1;
+    let mut index = 0;
+    loop {
+        let mut lines = draw(&frames[index], true);
+        lines.push(String::new());
+        lines.push(format!("Frame {} of {}, ←/→ to step, Home/End to jump, q to quit", index + 1, frames.len()));
+        show(out, &lines)?;
+
+        let key = match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            _ => continue,
+        };
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Left | KeyCode::Char('h') | KeyCode::Backspace => index = index.saturating_sub(1),
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Char(' ') => index = (index + 1).min(last),
+            KeyCode::PageUp => index = index.saturating_sub(10),
+            KeyCode::PageDown => index = (index + 10).min(last),
+            KeyCode::Home => index = 0,
+            KeyCode::End => index = last,
+            _ => {}
+        }
+    }
+}
+
+// Plays like the wrapped player and redraws the game in the terminal after every map update
+pub struct LiveViewer<P> {
+    player: P,
+    timeline: Timeline,
+}
+
+impl<P: Player> LiveViewer<P> {
+    pub fn new(player: P) -> LiveViewer<P> {
+        LiveViewer { player, timeline: Timeline::new() }
+    }
+}
+
+impl<P: Player> Player for LiveViewer<P> {
+    fn get_next_move(&mut self, map: &Map, player_id: &str) -> Direction {
+        self.player.get_next_move(map, player_id)
+    }
+
+    fn get_next_move_with_deadline(&mut self, map: &Map, player_id: &str, deadline: Instant) -> Direction {
+        self.player.get_next_move_with_deadline(map, player_id, deadline)
+    }
+
+    fn on_message(&mut self, message: &InboundMessage) {
+        self.player.on_message(message);
+        if self.timeline.push(message) {
+            if let Some(frame) = self.timeline.frames.last() {
+                if let Err(error) = show(&mut io::stdout(), &draw(frame, true)) {
+                    warn!("Failed to draw the game: {}", error);
+                }
+            }
+        }
+    }
+
+    fn on_reconnect(&mut self, attempt: u32) {
+        self.player.on_reconnect(attempt)
+    }
+
+    fn on_heartbeat(&mut self, round_trip: Duration) {
+        self.player.on_heartbeat(round_trip)
+    }
+
+    fn on_registered(&mut self, player_id: &str, game_mode: GameMode) {
+        self.player.on_registered(player_id, game_mode)
+    }
+
+    fn on_game_starting(&mut self, game_settings: &GameSettings) {
+        self.player.on_game_starting(game_settings)
+    }
+
+    fn on_snake_dead(&mut self, player_id: &str, death_reason: DeathReason) {
+        self.player.on_snake_dead(player_id, death_reason)
+    }
+
+    fn on_game_ended(&mut self, player_winner_id: &str, map: &Map) {
+        self.player.on_game_ended(player_winner_id, map)
+    }
+
+    fn on_game_result(&mut self, player_ranks: &[PlayerRank]) {
+        self.player.on_game_result(player_ranks)
+    }
+
+    fn on_tournament_ended(&mut self, player_winner_id: &str, game_result: &[GameResult]) {
+        self.player.on_tournament_ended(player_winner_id, game_result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{replay::Origin, utils::test::get_map};
+    use serde_json::{json, to_value};
+
+    const MAP_WIDTH: i32 = 3;
+
+    // Snake n is named snake-n, has n times `points` points and its tail protected for n ticks
+    fn scored(mut map: Map, points: i32) -> Map {
+        for (index, snake) in map.snake_infos.iter_mut().enumerate() {
+            snake.name = format!("snake-{}", index);
+            snake.points = points * index as i32;
+            snake.tail_protected_for_game_ticks = index as u32;
+        }
+        map
+    }
+
+    fn server(message: serde_json::Value) -> ReplayEntry {
+        ReplayEntry { timestamp_ms: 0, origin: Origin::Server, message }
+    }
+
+    fn map_update(game_tick: u32, map: &Map) -> ReplayEntry {
+        server(json!({"type": "se.cygni.snake.api.event.MapUpdateEvent", "receivingPlayerId": "0",
+                      "gameId": "game", "gameTick": game_tick, "map": to_value(map).unwrap()}))
+    }
+
+    #[test]
+    fn replay_becomes_one_frame_per_tick() {
+        let alive = scored(
+            get_map(
+                MAP_WIDTH,
+                2,
+                &[("0", &[Coordinate(0, 0)]), ("1", &[Coordinate(2, 1), Coordinate(2, 0)])],
+                &[],
+                &[],
+            ),
+            1,
+        );
+        let dead =
+            scored(get_map(MAP_WIDTH, 2, &[("0", &[]), ("1", &[Coordinate(1, 1), Coordinate(2, 1)])], &[], &[]), 1);
+        let entries = vec![
+            map_update(0, &alive),
+            server(json!({"type": "se.cygni.snake.api.event.SnakeDeadEvent", "receivingPlayerId": "0",
+                          "gameId": "game", "gameTick": 1, "playerId": "0", "x": 0, "y": 0,
+                          "deathReason": "CollisionWithWall"})),
+            map_update(1, &dead),
+            server(json!({"type": "se.cygni.snake.api.event.GameEndedEvent", "receivingPlayerId": "0",
+                          "playerWinnerId": "1", "playerWinnerName": "snake-1", "gameId": "game",
+                          "gameTick": 1, "map": to_value(&dead).unwrap()})),
+        ];
+        let timeline = Timeline::from_replay(&entries);
+
+        assert_eq!(vec![0, 1, 1], timeline.frames.iter().map(|frame| frame.game_tick).collect::<Vec<_>>());
+        assert!(timeline.frames[0].deaths.is_empty());
+        assert_eq!(DeathReason::CollisionWithWall, timeline.frames[1].deaths[0].reason);
+        assert_eq!(None, timeline.frames[1].player_winner_id);
+        assert_eq!(Some("1".to_string()), timeline.frames[2].player_winner_id);
+    }
+
+    #[test]
+    fn frame_shows_scoreboard_and_deaths() {
+        let map =
+            scored(get_map(MAP_WIDTH, 2, &[("0", &[]), ("1", &[Coordinate(1, 1), Coordinate(2, 1)])], &[], &[]), 5);
+        let death = Death {
+            player_id: "0".to_string(),
+            game_tick: 3,
+            coordinate: Coordinate(0, 0),
+            reason: DeathReason::CollisionWithWall,
+        };
+        let frame = Frame { game_tick: 4, map, deaths: vec![death], player_winner_id: Some("1".to_string()) };
+
+        assert_eq!(
+            vec![
+                "Tick 4, won by snake-1",
+                "#####",
+                "#...#",
+                "#.Bb#",
+                "#####",
+                "",
+                "B snake-1             5 points   2 long  tail protected for 1 ticks",
+                "A snake-0             0 points   0 long  dead",
+                "",
+                "Tick 3: snake-0 died at Coordinate(0, 0), CollisionWithWall",
+            ],
+            draw(&frame, false)
+        );
+    }
+}